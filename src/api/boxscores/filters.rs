@@ -0,0 +1,509 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio_postgres::types::ToSql;
+use utoipa::ToSchema;
+
+use super::models::{SortExpression, SortField};
+use crate::api::seasons::{parse_season, SeasonType};
+use crate::api::teams::resolve_team;
+
+// Most conditions in one filter tree
+const MAX_CONDITIONS: usize = 50;
+
+pub type SqlParam = Box<dyn ToSql + Sync + Send>;
+
+/// Boolean combination of conditions: {"and": [...]}, {"or": [...]}, {"not": {...}} or a single
+/// condition like {"field": "pts", "op": "gte", "value": 30}
+#[derive(Deserialize, Serialize, Clone, ToSchema, JsonSchema)]
+#[serde(untagged)]
+pub enum Filter {
+    Group(FilterGroup),
+    Condition(Condition),
+}
+
+#[derive(Deserialize, Serialize, Clone, ToSchema, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+#[schema(no_recursion)]
+pub enum FilterGroup {
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+}
+
+#[derive(Deserialize, Serialize, Clone, ToSchema, JsonSchema)]
+pub struct Condition {
+    /// A stat, derived metric or expression, or one of the text fields
+    pub field: ConditionField,
+    pub op: CompareOp,
+    /// Number or string to compare with, or a list of them for in
+    #[schema(value_type = Object)]
+    pub value: Value,
+
+    // Filled in by the player resolver for player eq, ne and in, never taken from the request
+    #[serde(skip)]
+    #[schema(ignore)]
+    #[schemars(skip)]
+    pub resolved_player_ids: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Serialize, Clone, ToSchema, JsonSchema)]
+#[serde(untagged)]
+pub enum ConditionField {
+    Text(TextField),
+    Expression(SortExpression),
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, ToSchema, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TextField {
+    Player,
+    Team,
+    Opponent,
+    Season,
+    SeasonType,
+    PlayerId,
+    GameId,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, ToSchema, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    In,
+    Contains,
+}
+
+impl CompareOp {
+    fn as_sql(&self) -> &str {
+        match self {
+            CompareOp::Eq => "=",
+            CompareOp::Ne => "<>",
+            CompareOp::Gt => ">",
+            CompareOp::Gte => ">=",
+            CompareOp::Lt => "<",
+            CompareOp::Lte => "<=",
+            CompareOp::In => "IN",
+            CompareOp::Contains => "LIKE",
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            CompareOp::Eq => "eq",
+            CompareOp::Ne => "ne",
+            CompareOp::Gt => "gt",
+            CompareOp::Gte => "gte",
+            CompareOp::Lt => "lt",
+            CompareOp::Lte => "lte",
+            CompareOp::In => "in",
+            CompareOp::Contains => "contains",
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        match self {
+            CompareOp::Eq => "is",
+            CompareOp::Ne => "is not",
+            CompareOp::Gt => "is more than",
+            CompareOp::Gte => "is at least",
+            CompareOp::Lt => "is less than",
+            CompareOp::Lte => "is at most",
+            CompareOp::In => "is one of",
+            CompareOp::Contains => "contains",
+        }
+    }
+
+    fn is_ordering(&self) -> bool {
+        matches!(self, CompareOp::Gt | CompareOp::Gte | CompareOp::Lt | CompareOp::Lte)
+    }
+}
+
+impl TextField {
+    pub fn label(&self) -> &str {
+        match self {
+            TextField::Player => "player",
+            TextField::Team => "team",
+            TextField::Opponent => "opponent",
+            TextField::Season => "season",
+            TextField::SeasonType => "season type",
+            TextField::PlayerId => "player id",
+            TextField::GameId => "game id",
+        }
+    }
+}
+
+impl Filter {
+    // Checks fields, operators and values, and rewrites seasons and season types into the
+    // forms stored in the database
+    pub fn normalize(&mut self) -> Result<(), String> {
+        let conditions = self.condition_count();
+        if conditions > MAX_CONDITIONS {
+            return Err(format!("filter has {} conditions, at most {} are allowed", conditions, MAX_CONDITIONS));
+        }
+        self.normalize_node()
+    }
+
+    fn normalize_node(&mut self) -> Result<(), String> {
+        match self {
+            Filter::Group(FilterGroup::And(items)) | Filter::Group(FilterGroup::Or(items)) => {
+                if items.is_empty() {
+                    return Err("and/or groups need at least one condition".to_string());
+                }
+                items.iter_mut().try_for_each(Filter::normalize_node)
+            }
+            Filter::Group(FilterGroup::Not(inner)) => inner.normalize_node(),
+            Filter::Condition(condition) => condition.normalize(),
+        }
+    }
+
+    // Conditions comparing player names exactly, which the player resolver maps to ids
+    pub fn player_conditions(&mut self) -> Vec<&mut Condition> {
+        let mut found = Vec::new();
+        let mut pending = vec![self];
+        while let Some(filter) = pending.pop() {
+            match filter {
+                Filter::Group(FilterGroup::And(items)) | Filter::Group(FilterGroup::Or(items)) => {
+                    pending.extend(items.iter_mut().rev());
+                }
+                Filter::Group(FilterGroup::Not(inner)) => pending.push(inner),
+                Filter::Condition(condition) => {
+                    if matches!(condition.field, ConditionField::Text(TextField::Player))
+                        && condition.op != CompareOp::Contains
+                    {
+                        found.push(condition);
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    fn condition_count(&self) -> usize {
+        match self {
+            Filter::Group(FilterGroup::And(items)) | Filter::Group(FilterGroup::Or(items)) => {
+                items.iter().map(Filter::condition_count).sum()
+            }
+            Filter::Group(FilterGroup::Not(inner)) => inner.condition_count(),
+            Filter::Condition(_) => 1,
+        }
+    }
+
+    // SQL for a normalized filter. Values are bound as parameters numbered after the ones
+    // already in params; team names resolve against season like the team filter does.
+    pub fn to_sql(&self, season: Option<&str>, params: &mut Vec<SqlParam>) -> String {
+        match self {
+            Filter::Group(FilterGroup::And(items)) => join(items, " AND ", season, params),
+            Filter::Group(FilterGroup::Or(items)) => join(items, " OR ", season, params),
+            Filter::Group(FilterGroup::Not(inner)) => format!("NOT ({})", inner.to_sql(season, params)),
+            Filter::Condition(condition) => condition.to_sql(season, params),
+        }
+    }
+}
+
+fn join(items: &[Filter], separator: &str, season: Option<&str>, params: &mut Vec<SqlParam>) -> String {
+    let parts: Vec<String> = items
+        .iter()
+        .map(|item| format!("({})", item.to_sql(season, params)))
+        .collect();
+    parts.join(separator)
+}
+
+fn bind(params: &mut Vec<SqlParam>, value: SqlParam) -> String {
+    params.push(value);
+    format!("${}", params.len())
+}
+
+impl Condition {
+    // Text comparisons apply to game_date, stored as 'YYYY-MM-DD' text
+    fn is_text(&self) -> bool {
+        matches!(
+            self.field,
+            ConditionField::Text(_) | ConditionField::Expression(SortExpression::Field(SortField::GameDate))
+        )
+    }
+
+    pub fn values(&self) -> Vec<&Value> {
+        match &self.value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        }
+    }
+
+    fn normalize(&mut self) -> Result<(), String> {
+        let field = match &self.field {
+            ConditionField::Text(field) => field.label().to_string(),
+            ConditionField::Expression(expr) => {
+                expr.validate()?;
+                self.field = ConditionField::Expression(expr.clone().normalized());
+                match &self.field {
+                    ConditionField::Expression(SortExpression::Field(field)) => field.as_sql().to_string(),
+                    _ => "expression".to_string(),
+                }
+            }
+        };
+        let op = self.op.name();
+
+        match (&self.value, self.op) {
+            (Value::Array(values), CompareOp::In) if !values.is_empty() => {}
+            (_, CompareOp::In) => return Err(format!("{} {} needs a non-empty list of values", field, op)),
+            (Value::Array(_), _) => return Err(format!("{} {} takes a single value, use in for a list", field, op)),
+            _ => {}
+        }
+
+        match &self.field {
+            ConditionField::Text(TextField::Player) if self.op.is_ordering() => {
+                return Err(format!("player can't be compared with {}", op));
+            }
+            ConditionField::Text(TextField::Team | TextField::Opponent | TextField::SeasonType | TextField::PlayerId | TextField::GameId)
+                if self.op.is_ordering() || self.op == CompareOp::Contains =>
+            {
+                return Err(format!("{} can't be compared with {}", field, op));
+            }
+            ConditionField::Text(TextField::Season) if self.op == CompareOp::Contains => {
+                return Err(format!("season can't be compared with {}", op));
+            }
+            ConditionField::Expression(_) if self.op == CompareOp::Contains && !self.is_text() => {
+                return Err(format!("{} can't be compared with {}", field, op));
+            }
+            _ => {}
+        }
+
+        let is_text = self.is_text();
+        for value in self.values() {
+            match value {
+                Value::Number(n) if !is_text && n.as_f64().is_some_and(f64::is_finite) => {}
+                Value::String(_) if is_text => {}
+                _ if is_text => return Err(format!("{} values must be strings, got {}", field, value)),
+                _ => return Err(format!("{} values must be numbers, got {}", field, value)),
+            }
+        }
+
+        // Stored forms: 'YYYY-YY' seasons and season type names
+        let rewrite = |value: &Value, f: &dyn Fn(&str) -> Result<String, String>| -> Result<Value, String> {
+            Ok(Value::String(f(value.as_str().unwrap_or_default())?))
+        };
+        let rewrite_all = |value: &Value, f: &dyn Fn(&str) -> Result<String, String>| -> Result<Value, String> {
+            match value {
+                Value::Array(values) => values
+                    .iter()
+                    .map(|v| rewrite(v, f))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(Value::Array),
+                value => rewrite(value, f),
+            }
+        };
+        match self.field {
+            ConditionField::Text(TextField::Season) => {
+                self.value = rewrite_all(&self.value, &parse_season)?;
+            }
+            ConditionField::Text(TextField::SeasonType) => {
+                self.value = rewrite_all(&self.value, &|v| {
+                    serde_json::from_value::<SeasonType>(Value::String(v.to_lowercase()))
+                        .map(|t| serde_json::to_value(t).ok().and_then(|t| t.as_str().map(str::to_string)).unwrap_or_default())
+                        .map_err(|_| format!("Invalid season type '{}': expected preseason, regular_season, playoffs or play_in", v))
+                })?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn to_sql(&self, season: Option<&str>, params: &mut Vec<SqlParam>) -> String {
+        let strings: Vec<String> = self
+            .values()
+            .iter()
+            .map(|v| v.as_str().unwrap_or_default().to_string())
+            .collect();
+        let first = strings.first().cloned().unwrap_or_default();
+        let negate = |sql: String, op: CompareOp| if op == CompareOp::Ne { format!("NOT ({})", sql) } else { sql };
+
+        match &self.field {
+            ConditionField::Text(field @ (TextField::Team | TextField::Opponent)) => {
+                let column = match field {
+                    TextField::Team => "team",
+                    // match_up is "LAL vs. BOS" at home and "LAL @ BOS" away
+                    _ => "split_part(match_up, ' ', 3)",
+                };
+                let mut abbreviations: Vec<String> = Vec::new();
+                for name in &strings {
+                    match resolve_team(name, season) {
+                        Some(resolved) => abbreviations.extend(resolved.iter().map(|a| a.to_string())),
+                        None => abbreviations.push(name.clone()),
+                    }
+                }
+                let placeholder = bind(params, Box::new(abbreviations));
+                negate(format!("{} = ANY({})", column, placeholder), self.op)
+            }
+            ConditionField::Text(TextField::Player) => match self.op {
+                CompareOp::Contains => {
+                    let pattern = format!("%{}%", first.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+                    format!("player ILIKE {}", bind(params, Box::new(pattern)))
+                }
+                _ => match &self.resolved_player_ids {
+                    Some(ids) => negate(format!("player_id = ANY({})", bind(params, Box::new(ids.clone()))), self.op),
+                    None => {
+                        let names: Vec<String> = strings.iter().map(|s| s.to_lowercase()).collect();
+                        negate(format!("lower(player) = ANY({})", bind(params, Box::new(names))), self.op)
+                    }
+                },
+            },
+            ConditionField::Text(TextField::SeasonType) => {
+                let prefixes: Vec<String> = strings
+                    .iter()
+                    .filter_map(|s| serde_json::from_value::<SeasonType>(Value::String(s.clone())).ok())
+                    .map(|t| t.game_id_prefix().to_string())
+                    .collect();
+                negate(format!("left(game_id, 3) = ANY({})", bind(params, Box::new(prefixes))), self.op)
+            }
+            ConditionField::Text(field) => {
+                let column = match field {
+                    TextField::Season => "season",
+                    TextField::PlayerId => "player_id",
+                    _ => "game_id",
+                };
+                match self.op {
+                    CompareOp::In | CompareOp::Eq | CompareOp::Ne => {
+                        negate(format!("{} = ANY({})", column, bind(params, Box::new(strings))), self.op)
+                    }
+                    op => format!("{} {} {}", column, op.as_sql(), bind(params, Box::new(first))),
+                }
+            }
+            ConditionField::Expression(expr) if self.is_text() => match self.op {
+                CompareOp::In => format!("{} = ANY({})", expr.as_sql(), bind(params, Box::new(strings))),
+                CompareOp::Contains => {
+                    let pattern = format!("%{}%", first.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+                    format!("{} LIKE {}", expr.as_sql(), bind(params, Box::new(pattern)))
+                }
+                op => format!("{} {} {}", expr.as_sql(), op.as_sql(), bind(params, Box::new(first))),
+            },
+            ConditionField::Expression(expr) => {
+                let numbers: Vec<f64> = self.values().iter().filter_map(|v| v.as_f64()).collect();
+                match self.op {
+                    CompareOp::In => format!("{} = ANY({}::float8[])", expr.as_sql(), bind(params, Box::new(numbers))),
+                    op => format!(
+                        "{} {} {}::float8",
+                        expr.as_sql(),
+                        op.as_sql(),
+                        bind(params, Box::new(numbers.first().copied().unwrap_or_default()))
+                    ),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn normalized(filter: Value) -> Result<Filter, String> {
+        let mut filter: Filter = serde_json::from_value(filter).map_err(|e| e.to_string())?;
+        filter.normalize()?;
+        Ok(filter)
+    }
+
+    // SQL and the debug form of the bound values, after `prior` params already bound
+    fn bound(filter: Value, prior: usize) -> (String, Vec<String>) {
+        let filter = normalized(filter).unwrap();
+        let mut params: Vec<SqlParam> = (0..prior).map(|i| Box::new(i as i64) as SqlParam).collect();
+        let sql = filter.to_sql(None, &mut params);
+        (sql, params[prior..].iter().map(|p| format!("{:?}", p)).collect())
+    }
+
+    #[test]
+    fn generates_sql() {
+        let (sql, values) = bound(json!({"field": "pts", "op": "gte", "value": 30}), 0);
+        assert_eq!(sql, "pts >= $1::float8");
+        assert_eq!(values, ["30.0"]);
+
+        let (sql, values) = bound(json!({"field": "player", "op": "in", "value": ["Stephen Curry", "Kevin Durant"]}), 0);
+        assert_eq!(sql, "lower(player) = ANY($1)");
+        assert_eq!(values, [r#"["stephen curry", "kevin durant"]"#]);
+
+        let (sql, values) = bound(json!({"field": "player", "op": "contains", "value": "50%_"}), 0);
+        assert_eq!(sql, "player ILIKE $1");
+        assert_eq!(values, [r#""%50\\%\\_%""#]);
+
+        let (sql, values) = bound(json!({"field": "opponent", "op": "ne", "value": "Celtics"}), 0);
+        assert_eq!(sql, "NOT (split_part(match_up, ' ', 3) = ANY($1))");
+        assert_eq!(values, [r#"["BOS"]"#]);
+
+        let (sql, values) = bound(json!({"field": "season", "op": "eq", "value": "2023"}), 0);
+        assert_eq!(sql, "season = ANY($1)");
+        assert_eq!(values, [r#"["2022-23"]"#]);
+
+        let (sql, _) = bound(json!({"field": "season_type", "op": "eq", "value": "Playoffs"}), 0);
+        assert_eq!(sql, "left(game_id, 3) = ANY($1)");
+    }
+
+    #[test]
+    fn numbers_placeholders_after_existing_params() {
+        let filter = json!({"or": [
+            {"and": [
+                {"field": "pts", "op": "gte", "value": 30},
+                {"field": "ast", "op": "gte", "value": 10}
+            ]},
+            {"not": {"field": "team", "op": "in", "value": ["LAL", "BOS"]}}
+        ]});
+        let (sql, values) = bound(filter.clone(), 0);
+        assert_eq!(sql, "((pts >= $1::float8) AND (ast >= $2::float8)) OR (NOT (team = ANY($3)))");
+        assert_eq!(values, ["30.0", "10.0", r#"["LAL", "BOS"]"#]);
+
+        let (sql, values) = bound(filter, 2);
+        assert_eq!(sql, "((pts >= $3::float8) AND (ast >= $4::float8)) OR (NOT (team = ANY($5)))");
+        assert_eq!(values.len(), 3);
+    }
+
+    #[test]
+    fn uses_resolved_player_ids() {
+        let mut filter = normalized(json!({"field": "player", "op": "ne", "value": "Curry"})).unwrap();
+        let conditions = filter.player_conditions();
+        assert_eq!(conditions.len(), 1);
+        conditions.into_iter().for_each(|c| c.resolved_player_ids = Some(vec!["201939".to_string()]));
+
+        let mut params = Vec::new();
+        assert_eq!(filter.to_sql(None, &mut params), "NOT (player_id = ANY($1))");
+        assert_eq!(format!("{:?}", params[0]), r#"["201939"]"#);
+
+        let mut filter = normalized(json!({"and": [
+            {"field": "player", "op": "contains", "value": "Cur"},
+            {"not": {"field": "player", "op": "in", "value": ["Curry", "Durant"]}},
+            {"field": "team", "op": "eq", "value": "GSW"}
+        ]}))
+        .unwrap();
+        let conditions = filter.player_conditions();
+        assert_eq!(conditions.len(), 1);
+        assert!(conditions[0].op == CompareOp::In);
+    }
+
+    #[test]
+    fn rejects_invalid_conditions() {
+        let rejected = [
+            (json!({"field": "player", "op": "gt", "value": "Curry"}), "player can't be compared with gt"),
+            (json!({"field": "team", "op": "contains", "value": "La"}), "team can't be compared with contains"),
+            (json!({"field": "opponent", "op": "lte", "value": "BOS"}), "opponent can't be compared with lte"),
+            (json!({"field": "season", "op": "contains", "value": "2023"}), "season can't be compared with contains"),
+            (json!({"field": "pts", "op": "contains", "value": 3}), "pts can't be compared with contains"),
+            (json!({"field": "pts", "op": "in", "value": []}), "pts in needs a non-empty list of values"),
+            (json!({"field": "pts", "op": "eq", "value": [1, 2]}), "pts eq takes a single value, use in for a list"),
+            (json!({"field": "pts", "op": "eq", "value": "30"}), r#"pts values must be numbers, got "30""#),
+            (json!({"field": "team", "op": "eq", "value": 1}), "team values must be strings, got 1"),
+            (json!({"and": []}), "and/or groups need at least one condition"),
+        ];
+        for (filter, error) in rejected {
+            assert_eq!(normalized(filter.clone()).err().as_deref(), Some(error), "{}", filter);
+        }
+
+        let many: Vec<Value> = (0..=MAX_CONDITIONS).map(|_| json!({"field": "pts", "op": "gt", "value": 1})).collect();
+        assert!(normalized(json!({"and": many})).err().unwrap_or_default().contains("at most 50"));
+        assert!(normalized(json!({"field": "season", "op": "eq", "value": "someday"})).is_err());
+        assert!(normalized(json!({"field": "season_type", "op": "eq", "value": "finals"})).is_err());
+    }
+}
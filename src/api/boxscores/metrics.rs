@@ -0,0 +1,48 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// Stats computed from the box score columns, usable anywhere a field is
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, ToSchema, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DerivedMetric {
+    TsPercent,
+    EfgPercent,
+    PtsPerFga,
+    PtsPerMin,
+    AstToTov,
+    Stocks,
+    Pra,
+    GameScore,
+}
+
+impl DerivedMetric {
+    // Divisions return NULL instead of failing when the denominator is 0
+    pub fn as_sql(&self) -> &str {
+        match self {
+            DerivedMetric::TsPercent => "(100 * pts::float8 / NULLIF(2 * (fga + 0.44 * fta), 0))",
+            DerivedMetric::EfgPercent => "(100 * (fgm + 0.5 * three_pm)::float8 / NULLIF(fga, 0))",
+            DerivedMetric::PtsPerFga => "(pts::float8 / NULLIF(fga, 0))",
+            DerivedMetric::PtsPerMin => "(pts::float8 / NULLIF(min, 0))",
+            DerivedMetric::AstToTov => "(ast::float8 / NULLIF(tov, 0))",
+            DerivedMetric::Stocks => "(stl + blk)",
+            DerivedMetric::Pra => "(pts + reb + ast)",
+            DerivedMetric::GameScore => {
+                "(pts + 0.4 * fgm - 0.7 * fga - 0.4 * (fta - ftm) + 0.7 * oreb + 0.3 * dreb + stl + 0.7 * ast + 0.7 * blk - 0.4 * pf - tov)::float8"
+            }
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        match self {
+            DerivedMetric::TsPercent => "true shooting percentage",
+            DerivedMetric::EfgPercent => "effective field goal percentage",
+            DerivedMetric::PtsPerFga => "points per shot attempt",
+            DerivedMetric::PtsPerMin => "points per minute",
+            DerivedMetric::AstToTov => "assist-to-turnover ratio",
+            DerivedMetric::Stocks => "steals plus blocks",
+            DerivedMetric::Pra => "points, rebounds and assists",
+            DerivedMetric::GameScore => "game score",
+        }
+    }
+}
@@ -1,5 +1,8 @@
+pub mod filters;
+pub mod metrics;
 pub mod models;
 pub mod routes;
+pub mod schema;
 
-pub use models::{BoxScore, CountResponse};
-pub use routes::{get_boxscores, get_count};
+pub use models::{BoxScore, CountResponse, GroupCount};
+pub use routes::{get_boxscores, get_count, search_boxscores};
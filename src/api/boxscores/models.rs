@@ -1,7 +1,17 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
 use utoipa::{IntoParams, ToSchema};
 
-#[derive(Deserialize, Serialize, Clone, ToSchema)]
+use super::filters::Filter;
+use super::metrics::DerivedMetric;
+use crate::api::cursor::Cursor;
+use crate::api::explain::Explanation;
+use crate::api::seasons::{parse_season, SeasonType};
+use crate::llm::{LlmUsage, PromptRef};
+
+#[derive(Deserialize, Serialize, Clone, ToSchema, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum SortField {
     GameDate,
@@ -55,42 +65,487 @@ impl SortField {
             SortField::Min => "min",
         }
     }
+
+    // Name used in plain-English query explanations
+    pub fn label(&self) -> &str {
+        match self {
+            SortField::GameDate => "game date",
+            SortField::Pts => "points",
+            SortField::Reb => "rebounds",
+            SortField::Ast => "assists",
+            SortField::Stl => "steals",
+            SortField::Blk => "blocks",
+            SortField::Fgm => "field goals made",
+            SortField::Fga => "field goals attempted",
+            SortField::FgPercent => "field goal percentage",
+            SortField::ThreePm => "three-pointers made",
+            SortField::ThreePa => "three-pointers attempted",
+            SortField::ThreePPercent => "three-point percentage",
+            SortField::Ftm => "free throws made",
+            SortField::Fta => "free throws attempted",
+            SortField::FtPercent => "free throw percentage",
+            SortField::Oreb => "offensive rebounds",
+            SortField::Dreb => "defensive rebounds",
+            SortField::Tov => "turnovers",
+            SortField::Pf => "personal fouls",
+            SortField::PlusMinus => "plus/minus",
+            SortField::Fp => "fantasy points",
+            SortField::Min => "minutes",
+        }
+    }
 }
 
-#[derive(Deserialize, Serialize, Clone, ToSchema)]
+#[derive(Deserialize, Serialize, Clone, ToSchema, JsonSchema)]
 pub struct WeightedField {
     pub field: SortField,
     pub weight: f64,
 }
 
-#[derive(Deserialize, Serialize, Clone, ToSchema)]
+// Most terms in a weighted sum, and operands of one add, sub or mul
+const MAX_TERMS: usize = 10;
+
+// Most fields, constants and operations in one expression
+const MAX_EXPRESSION_NODES: usize = 32;
+
+/// A field or derived metric name, a number, {"terms": [...]} or an {"add"|"sub"|"mul"|"div": [...]}
+/// object over nested expressions, e.g. {"div": ["pts", {"add": ["fga", {"mul": [0.44, "fta"]}]}]}
+#[derive(Deserialize, Serialize, Clone, ToSchema, JsonSchema)]
 #[serde(untagged)]
 pub enum SortExpression {
     Field(SortField),
+    Metric(DerivedMetric),
+    Constant(f64),
     Sum { terms: Vec<WeightedField> },
+    Op(Operation),
+}
+
+#[derive(Deserialize, Serialize, Clone, ToSchema, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+#[schema(no_recursion)]
+pub enum Operation {
+    Add(Vec<SortExpression>),
+    /// The first operand minus the others
+    Sub(Vec<SortExpression>),
+    Mul(Vec<SortExpression>),
+    /// Exactly two operands, NULL when the divisor is 0
+    #[serde(alias = "ratio")]
+    Div(Vec<SortExpression>),
 }
 
 impl SortExpression {
+    // Only field and metric names from the whitelists and numbers reach the SQL
     pub fn as_sql(&self) -> String {
         match self {
             SortExpression::Field(f) => f.as_sql().to_string(),
+            SortExpression::Metric(m) => m.as_sql().to_string(),
+            SortExpression::Constant(c) => c.to_string(),
             SortExpression::Sum { terms } => {
                 let parts: Vec<String> = terms.iter().map(|w| {
                     format!("({} * {})", w.weight, w.field.as_sql())
                 }).collect();
                 format!("({})", parts.join(" + "))
             }
+            SortExpression::Op(op) => {
+                let parts: Vec<String> = op.operands().iter().map(SortExpression::as_sql).collect();
+                match op {
+                    Operation::Add(_) => format!("({})", parts.join(" + ")),
+                    Operation::Sub(_) => format!("({})", parts.join(" - ")),
+                    Operation::Mul(_) => format!("({})", parts.join(" * ")),
+                    Operation::Div(_) => format!("(({})::float8 / NULLIF({}, 0))", parts[0], parts[1]),
+                }
+            }
+        }
+    }
+
+    // Rejects expressions that would produce invalid SQL (non-finite numbers, empty sums,
+    // wrong operand counts) or unreasonably large queries
+    pub fn validate(&self) -> Result<(), String> {
+        let nodes = self.node_count();
+        if nodes > MAX_EXPRESSION_NODES {
+            return Err(format!(
+                "expression has {} parts, at most {} are allowed",
+                nodes, MAX_EXPRESSION_NODES
+            ));
+        }
+        self.check()
+    }
+
+    fn check(&self) -> Result<(), String> {
+        match self {
+            SortExpression::Field(_) | SortExpression::Metric(_) => Ok(()),
+            SortExpression::Constant(c) => finite("constant", *c),
+            SortExpression::Sum { terms } => {
+                if terms.is_empty() {
+                    return Err("weighted sum needs at least one term".to_string());
+                }
+                if terms.len() > MAX_TERMS {
+                    return Err(format!(
+                        "weighted sum has {} terms, at most {} are allowed",
+                        terms.len(),
+                        MAX_TERMS
+                    ));
+                }
+                for (i, term) in terms.iter().enumerate() {
+                    finite(&format!("weight of {}", term.field.as_sql()), term.weight)?;
+                    if terms[..i].iter().any(|t| t.field.as_sql() == term.field.as_sql()) {
+                        return Err(format!(
+                            "{} appears more than once in the weighted sum; combine its weights into one term",
+                            term.field.as_sql()
+                        ));
+                    }
+                }
+                Ok(())
+            }
+            SortExpression::Op(op) => {
+                let operands = op.operands();
+                match op {
+                    Operation::Div(_) if operands.len() != 2 => {
+                        return Err(format!("div takes exactly 2 operands, got {}", operands.len()));
+                    }
+                    _ if operands.len() < 2 => {
+                        return Err(format!("{} takes at least 2 operands, got {}", op.name(), operands.len()));
+                    }
+                    _ if operands.len() > MAX_TERMS => {
+                        return Err(format!(
+                            "{} has {} operands, at most {} are allowed",
+                            op.name(),
+                            operands.len(),
+                            MAX_TERMS
+                        ));
+                    }
+                    _ => {}
+                }
+                operands.iter().try_for_each(SortExpression::check)
+            }
+        }
+    }
+
+    fn node_count(&self) -> usize {
+        match self {
+            SortExpression::Sum { terms } => 1 + terms.len(),
+            SortExpression::Op(op) => 1 + op.operands().iter().map(SortExpression::node_count).sum::<usize>(),
+            _ => 1,
+        }
+    }
+
+    // Canonical form of a valid expression, so equivalent expressions produce identical SQL:
+    // sum terms ordered by field without zero weights, a lone unit-weight term reduced to its
+    // field, and nested add and mul flattened with their operands ordered
+    pub fn normalized(self) -> SortExpression {
+        match self {
+            // Adding 0.0 turns -0 into 0
+            SortExpression::Constant(c) => SortExpression::Constant(c + 0.0),
+            SortExpression::Sum { terms } => {
+                let mut terms: Vec<WeightedField> = terms
+                    .into_iter()
+                    .filter(|term| term.weight != 0.0)
+                    .collect();
+                terms.sort_by(|a, b| a.field.as_sql().cmp(b.field.as_sql()));
+                match terms.as_slice() {
+                    [] => SortExpression::Constant(0.0),
+                    [term] if term.weight == 1.0 => SortExpression::Field(term.field.clone()),
+                    _ => SortExpression::Sum { terms },
+                }
+            }
+            SortExpression::Op(op) => {
+                let commutative = matches!(op, Operation::Add(_) | Operation::Mul(_));
+                let name = op.name().to_string();
+                let mut operands = Vec::new();
+                for operand in op.into_operands() {
+                    match operand.normalized() {
+                        SortExpression::Op(inner) if commutative && inner.name() == name => {
+                            operands.extend(inner.into_operands())
+                        }
+                        other => operands.push(other),
+                    }
+                }
+                if commutative {
+                    operands.sort_by_cached_key(SortExpression::as_sql);
+                }
+                SortExpression::Op(match name.as_str() {
+                    "add" => Operation::Add(operands),
+                    "sub" => Operation::Sub(operands),
+                    "mul" => Operation::Mul(operands),
+                    _ => Operation::Div(operands),
+                })
+            }
+            other => other,
+        }
+    }
+}
+
+fn finite(name: &str, value: f64) -> Result<(), String> {
+    if value.is_finite() {
+        Ok(())
+    } else {
+        Err(format!("{} must be a finite number, got {}", name, value))
+    }
+}
+
+impl Operation {
+    pub fn name(&self) -> &str {
+        match self {
+            Operation::Add(_) => "add",
+            Operation::Sub(_) => "sub",
+            Operation::Mul(_) => "mul",
+            Operation::Div(_) => "div",
+        }
+    }
+
+    pub fn operands(&self) -> &[SortExpression] {
+        match self {
+            Operation::Add(items) | Operation::Sub(items) | Operation::Mul(items) | Operation::Div(items) => items,
+        }
+    }
+
+    fn into_operands(self) -> Vec<SortExpression> {
+        match self {
+            Operation::Add(items) | Operation::Sub(items) | Operation::Mul(items) | Operation::Div(items) => items,
+        }
+    }
+}
+
+// Keeps rows whose expression value lies within [min, max]
+#[derive(Deserialize, Serialize, Clone, ToSchema, JsonSchema)]
+pub struct ExpressionFilter {
+    pub expr: SortExpression,
+    /// Lowest value kept
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    /// Highest value kept
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+}
+
+impl ExpressionFilter {
+    pub fn validate(&self) -> Result<(), String> {
+        self.expr.validate()?;
+        match (self.min, self.max) {
+            (None, None) => return Err("each condition needs a min or a max".to_string()),
+            (Some(min), Some(max)) if min > max => {
+                return Err(format!("min ({}) is greater than max ({})", min, max));
+            }
+            _ => {}
+        }
+        self.min.map_or(Ok(()), |min| finite("min", min))?;
+        self.max.map_or(Ok(()), |max| finite("max", max))
+    }
+
+    pub fn as_sql(&self) -> String {
+        let expr = self.expr.as_sql();
+        let mut sql = String::new();
+        if let Some(min) = self.min {
+            sql.push_str(&format!(" AND {} >= {}", expr, min));
+        }
+        if let Some(max) = self.max {
+            sql.push_str(&format!(" AND {} <= {}", expr, max));
+        }
+        sql
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, ToSchema, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, ToSchema, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum NullsOrder {
+    First,
+    Last,
+}
+
+#[derive(Deserialize, Serialize, Clone, ToSchema, JsonSchema)]
+pub struct SortKey {
+    /// Field or weighted sum of fields
+    pub by: SortExpression,
+    /// Direction of this key (default: the asc parameter)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir: Option<SortDirection>,
+    /// Where rows without a value go (default: last)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nulls: Option<NullsOrder>,
+}
+
+// One sort expression, or an ordered list of keys where each breaks the ties of the one
+// before. Query strings can write the list as "pts:desc,ast:desc,game_date:desc:nulls_first".
+#[derive(Serialize, Clone, ToSchema, JsonSchema)]
+#[serde(untagged)]
+pub enum SortBy {
+    One(SortExpression),
+    Keys(Vec<SortKey>),
+}
+
+impl SortBy {
+    pub fn keys(&self) -> Vec<SortKey> {
+        match self {
+            SortBy::One(expr) => vec![SortKey { by: expr.clone(), dir: None, nulls: None }],
+            SortBy::Keys(keys) => keys.clone(),
+        }
+    }
+
+    // Validates every key and puts its expression in canonical form
+    pub fn normalized(self) -> Result<Self, String> {
+        let keys = self
+            .keys()
+            .into_iter()
+            .map(|key| {
+                key.by.validate()?;
+                Ok(SortKey { by: key.by.normalized(), ..key })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(SortBy::from_keys(keys))
+    }
+
+    fn from_keys(mut keys: Vec<SortKey>) -> Self {
+        match keys.as_slice() {
+            [SortKey { dir: None, nulls: None, .. }] => SortBy::One(keys.remove(0).by),
+            _ => SortBy::Keys(keys),
+        }
+    }
+}
+
+impl From<SortExpression> for SortBy {
+    fn from(expr: SortExpression) -> Self {
+        SortBy::One(expr)
+    }
+}
+
+impl<'de> Deserialize<'de> for SortBy {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum KeyInput {
+            Key(SortKey),
+            Expression(SortExpression),
+            Text(String),
+        }
+
+        // Query strings can only pass expressions as JSON text
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Input {
+            One(KeyInput),
+            Keys(Vec<KeyInput>),
+        }
+
+        let inputs = match Input::deserialize(deserializer)? {
+            Input::One(input) => vec![input],
+            Input::Keys(inputs) => inputs,
+        };
+        let mut keys = Vec::new();
+        for input in inputs {
+            match input {
+                KeyInput::Key(key) => keys.push(key),
+                KeyInput::Expression(by) => keys.push(SortKey { by, dir: None, nulls: None }),
+                KeyInput::Text(text) if text.trim_start().starts_with(['{', '[']) => {
+                    let sort_by: SortBy = serde_json::from_str(&text)
+                        .map_err(|e| serde::de::Error::custom(format!("Invalid sort_by JSON: {}", e)))?;
+                    keys.extend(sort_by.keys());
+                }
+                KeyInput::Text(text) => keys.extend(parse_sort_keys(&text).map_err(serde::de::Error::custom)?),
+            }
         }
+        if keys.is_empty() {
+            return Err(serde::de::Error::custom("sort_by needs at least one key"));
+        }
+        Ok(SortBy::from_keys(keys))
     }
 }
 
-#[derive(Deserialize, Serialize, Clone, ToSchema)]
+// Parses "field[:asc|desc][:nulls_first|nulls_last]" keys separated by commas
+fn parse_sort_keys(text: &str) -> Result<Vec<SortKey>, String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .map(|key| {
+            let mut parts = key.split(':').map(str::trim);
+            let field = parts.next().unwrap_or_default();
+            let by = serde_json::from_value(Value::String(field.to_lowercase()))
+                .map_err(|_| format!("Unknown sort field '{}'", field))?;
+            let mut sort_key = SortKey { by, dir: None, nulls: None };
+            for part in parts {
+                match part.to_lowercase().as_str() {
+                    "asc" => sort_key.dir = Some(SortDirection::Asc),
+                    "desc" => sort_key.dir = Some(SortDirection::Desc),
+                    "nulls_first" | "first" => sort_key.nulls = Some(NullsOrder::First),
+                    "nulls_last" | "last" => sort_key.nulls = Some(NullsOrder::Last),
+                    other => {
+                        return Err(format!(
+                            "Invalid sort option '{}' in '{}': expected asc, desc, nulls_first or nulls_last",
+                            other, key
+                        ));
+                    }
+                }
+            }
+            Ok(sort_key)
+        })
+        .collect()
+}
+
+// A resolved sort key: its SQL, direction and NULL placement
+pub struct SortOrder {
+    pub sql: String,
+    pub asc: bool,
+    pub nulls_first: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default, ToSchema, JsonSchema)]
 pub struct SortParams {
-    pub sort_by: Option<SortExpression>,
+    /// Field, derived metric, expression, or ordered list of sort keys (default: game_date)
+    pub sort_by: Option<SortBy>,
+    /// Sort ascending (default: false, highest first). Applies to keys without their own dir.
     #[serde(default, deserialize_with = "deserialize_bool")]
+    #[schemars(with = "Option<bool>")]
     pub asc: Option<bool>,
 }
 
+impl SortParams {
+    pub fn ascending(&self) -> bool {
+        self.asc.unwrap_or(false)
+    }
+
+    pub fn keys(&self) -> Vec<SortKey> {
+        self.sort_by
+            .as_ref()
+            .map(SortBy::keys)
+            .unwrap_or_else(|| vec![SortKey { by: SortExpression::Field(SortField::GameDate), dir: None, nulls: None }])
+    }
+
+    pub fn orders(&self) -> Vec<SortOrder> {
+        self.keys()
+            .iter()
+            .map(|key| SortOrder {
+                sql: key.by.as_sql(),
+                asc: key.dir.map(|dir| dir == SortDirection::Asc).unwrap_or(self.ascending()),
+                nulls_first: key.nulls == Some(NullsOrder::First),
+            })
+            .collect()
+    }
+
+    // Identifies the ordering a cursor belongs to
+    pub fn signature(&self) -> String {
+        self.orders()
+            .iter()
+            .map(|order| {
+                format!(
+                    "{} {} nulls {}",
+                    order.sql,
+                    if order.asc { "asc" } else { "desc" },
+                    if order.nulls_first { "first" } else { "last" }
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
 fn deserialize_bool<'de, D>(deserializer: D) -> Result<Option<bool>, D::Error>
 where
     D: serde::Deserializer<'de>,
@@ -117,54 +572,349 @@ where
     }
 }
 
+// Takes the filters as a JSON value, or as JSON text in a query string
+fn deserialize_filters<'de, D>(deserializer: D) -> Result<Option<Vec<ExpressionFilter>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum FiltersInput {
+        Many(Vec<ExpressionFilter>),
+        One(ExpressionFilter),
+        Text(String),
+    }
+
+    match Option::<FiltersInput>::deserialize(deserializer)? {
+        Some(FiltersInput::Many(filters)) => Ok(Some(filters)),
+        Some(FiltersInput::One(filter)) => Ok(Some(vec![filter])),
+        Some(FiltersInput::Text(text)) => {
+            let filters = match serde_json::from_str(&text) {
+                Ok(FiltersInput::Many(filters)) => filters,
+                Ok(FiltersInput::One(filter)) => vec![filter],
+                _ => return Err(serde::de::Error::custom("Invalid where: expected JSON like [{\"expr\": {\"div\": [\"pts\", \"fga\"]}, \"min\": 1.5}]")),
+            };
+            Ok(Some(filters))
+        }
+        None => Ok(None),
+    }
+}
+
+// Takes the filter tree as a JSON value, or as JSON text in a query string
+fn deserialize_filter<'de, D>(deserializer: D) -> Result<Option<Filter>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum FilterInput {
+        Tree(Filter),
+        Text(String),
+    }
+
+    match Option::<FilterInput>::deserialize(deserializer)? {
+        Some(FilterInput::Tree(filter)) => Ok(Some(filter)),
+        Some(FilterInput::Text(text)) => serde_json::from_str(&text)
+            .map(Some)
+            .map_err(|e| serde::de::Error::custom(format!("Invalid filter JSON: {}", e))),
+        None => Ok(None),
+    }
+}
+
+// Most values in one list filter
+const MAX_LIST_VALUES: usize = 25;
+
+// Largest page, and largest limit on an export
+const MAX_LIMIT: i64 = 10_000;
+
+/// One value, or a list of them matching any
+#[derive(Deserialize, Serialize, Clone, PartialEq, ToSchema, JsonSchema)]
+#[serde(untagged)]
+pub enum ValueList {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl ValueList {
+    pub fn values(&self) -> Vec<&str> {
+        match self {
+            ValueList::One(value) => vec![value.as_str()],
+            ValueList::Many(values) => values.iter().map(String::as_str).collect(),
+        }
+    }
+
+    // Expands "in:[a, b]" (or in:["a", "b"]), and "a,b" when commas can't be part of a
+    // value, into a list. A list of one value becomes that value.
+    fn parsed(self, split_commas: bool) -> Result<Self, String> {
+        let values: Vec<String> = match self {
+            ValueList::Many(values) => values,
+            ValueList::One(value) => match value.trim().strip_prefix("in:") {
+                Some(list) => match serde_json::from_str::<Vec<String>>(list) {
+                    Ok(values) => values,
+                    Err(_) => {
+                        let inner = list
+                            .trim()
+                            .strip_prefix('[')
+                            .and_then(|l| l.strip_suffix(']'))
+                            .ok_or(format!("expected in:[a, b], got '{}'", value))?;
+                        inner
+                            .split(',')
+                            .map(|v| v.trim().trim_matches(|c| c == '"' || c == '\'').to_string())
+                            .collect()
+                    }
+                },
+                None if split_commas => value.split(',').map(str::to_string).collect(),
+                None => vec![value],
+            },
+        };
+
+        // Repeats are dropped wherever they are, keeping the first occurrence's position
+        let mut seen = HashSet::new();
+        let mut values: Vec<String> = values
+            .into_iter()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty() && seen.insert(v.clone()))
+            .collect();
+        match values.len() {
+            0 => Err("needs at least one value".to_string()),
+            1 => Ok(ValueList::One(values.remove(0))),
+            n if n > MAX_LIST_VALUES => Err(format!("has {} values, at most {} are allowed", n, MAX_LIST_VALUES)),
+            _ => Ok(ValueList::Many(values)),
+        }
+    }
+}
+
+impl From<String> for ValueList {
+    fn from(value: String) -> Self {
+        ValueList::One(value)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CountGroup {
+    Player,
+    Team,
+    Season,
+}
+
+impl CountGroup {
+    pub fn column(&self) -> &str {
+        match self {
+            CountGroup::Player => "player",
+            CountGroup::Team => "team",
+            CountGroup::Season => "season",
+        }
+    }
+
+    // Players are grouped by id as well, so two players sharing a name stay apart
+    pub fn id_column(&self) -> Option<&str> {
+        match self {
+            CountGroup::Player => Some("player_id"),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct CountParams {
+    /// Also count the matching box scores per player, team or season
+    pub group_by: Option<CountGroup>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct GroupCount {
+    pub value: String,
+    /// Player id, present when grouping by player
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub player_id: Option<String>,
+    pub count: i64,
+}
+
 #[derive(Serialize, ToSchema)]
 pub struct CountResponse {
     pub count: i64,
+    // Largest groups first, present when group_by was given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub groups: Option<Vec<GroupCount>>,
 }
 
-#[derive(Deserialize, Serialize, Clone, ToSchema, IntoParams)]
+#[derive(Deserialize, Serialize, Clone, Default, ToSchema, IntoParams, JsonSchema)]
 pub struct QueryParams {
     // Main stats
+    /// Minimum points
     pub pts: Option<i32>,
+    /// Minimum rebounds
     pub reb: Option<i32>,
+    /// Minimum assists
     pub ast: Option<i32>,
+    /// Minimum steals
     pub stl: Option<i32>,
+    /// Minimum blocks
     pub blk: Option<i32>,
 
     // Additional stats
+    /// Minimum field goals made
     pub fgm: Option<i32>,
+    /// Minimum field goals attempted
     pub fga: Option<i32>,
+    /// Minimum field goal percentage, stored as a percentage (45.5, not 0.455)
     pub fg_percent: Option<f64>,
+    /// Minimum three-pointers made
     pub three_pm: Option<i32>,
+    /// Minimum three-pointers attempted
     pub three_pa: Option<i32>,
+    /// Minimum three point percentage, stored as a percentage (38.2, not 0.382)
     pub three_p_percent: Option<f64>,
+    /// Minimum free throws made
     pub ftm: Option<i32>,
+    /// Minimum free throws attempted
     pub fta: Option<i32>,
+    /// Minimum free throw percentage, stored as a percentage (87.5, not 0.875)
     pub ft_percent: Option<f64>,
+    /// Minimum offensive rebounds
     pub oreb: Option<i32>,
+    /// Minimum defensive rebounds
     pub dreb: Option<i32>,
+    /// Minimum turnovers
     pub tov: Option<i32>,
+    /// Minimum personal fouls
     pub pf: Option<i32>,
+    /// Minimum plus/minus
     pub plus_minus: Option<i32>,
+    /// Minimum fantasy points
     pub fp: Option<f64>,
+    /// Minimum minutes played
     pub min: Option<i32>,
 
+    /// Conditions on computed expressions keeping rows whose value is within min and max, e.g. [{"expr": {"div": ["pts", "fga"]}, "min": 1.5}]
+    #[serde(rename = "where", default, deserialize_with = "deserialize_filters", skip_serializing_if = "Option::is_none")]
+    #[schemars(rename = "where", with = "Option<Vec<ExpressionFilter>>")]
+    #[param(rename = "where", value_type = Option<String>)]
+    pub filters: Option<Vec<ExpressionFilter>>,
+
+    /// Conditions combined with and, or and not, for what the minimums can't express, e.g. {"or": [{"field": "pts", "op": "gte", "value": 30}, {"field": "ast", "op": "gte", "value": 15}]} or {"not": {"field": "opponent", "op": "eq", "value": "BOS"}}
+    #[serde(default, deserialize_with = "deserialize_filter", skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<Filter>")]
+    #[param(value_type = Option<String>)]
+    pub filter: Option<Filter>,
+
     // Meta filters
+    /// Single season, e.g. '2024-25'. Also accepts '2024-2025', '24-25', '2025' (the 2024-25 season), 'this season' and 'last season'
     pub season: Option<String>,
-    pub player: Option<String>,
-    pub team: Option<String>,
-    pub player_id: Option<String>,
+    /// First season of a range, same formats as season
+    pub season_from: Option<String>,
+    /// Last season of a range, same formats as season
+    pub season_to: Option<String>,
+    /// Part of the season
+    pub season_type: Option<SeasonType>,
+    /// Player name or nickname, resolved against the players in the database (typos are tolerated).
+    /// Several players as a list, e.g. ["LeBron James", "Kevin Durant"], or in:[LeBron James, Kevin Durant] in a query string
+    #[param(value_type = Option<String>)]
+    pub player: Option<ValueList>,
+    /// Team the player played for: abbreviation, name, nickname or city (e.g. 'LAL', 'Lakers', 'Sixers', 'Seattle').
+    /// Several teams as a list or comma separated, e.g. 'LAL,BOS'
+    #[param(value_type = Option<String>)]
+    pub team: Option<ValueList>,
+    /// Opposing team: abbreviation, name, nickname or city (e.g. 'BOS', 'Celtics')
+    pub opponent: Option<String>,
+    /// Exact player id, or several as a list or comma separated
+    #[param(value_type = Option<String>)]
+    pub player_id: Option<ValueList>,
+    /// Exact game id
     pub game_id: Option<String>,
 
+    // Filled in by the player resolver, never taken from the request
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    #[param(ignore)]
+    #[schema(ignore)]
+    #[schemars(skip)]
+    pub resolved_player_ids: Option<Vec<String>>,
+
     // Pagination
+    /// Maximum number of results, 1 to 10000 (default: 50)
     pub limit: Option<i64>,
+    /// Number of results to skip
     pub offset: Option<i64>,
+    /// Opaque position from next_cursor or prev_cursor of a previous response, used instead of offset
+    #[schemars(skip)]
+    pub cursor: Option<String>,
+
+    // Decoded from cursor by normalize
+    #[serde(skip)]
+    #[param(ignore)]
+    #[schema(ignore)]
+    #[schemars(skip)]
+    pub page_cursor: Option<Cursor>,
 
     // Sorting
     #[serde(flatten)]
     pub sort: SortParams,
 }
 
+impl QueryParams {
+    // Validates and rewrites user supplied values into the forms stored in the database
+    pub fn normalize(&mut self) -> Result<(), String> {
+        for season in [&mut self.season, &mut self.season_from, &mut self.season_to] {
+            if let Some(value) = season.as_deref() {
+                *season = Some(parse_season(value)?);
+            }
+        }
+
+        if let (Some(from), Some(to)) = (&self.season_from, &self.season_to)
+            && from > to
+        {
+            return Err(format!("season_from ({}) is after season_to ({})", from, to));
+        }
+
+        if let Some(limit) = self.limit
+            && !(1..=MAX_LIMIT).contains(&limit)
+        {
+            return Err(format!("limit must be between 1 and {}, got {}", MAX_LIMIT, limit));
+        }
+        if let Some(offset) = self.offset
+            && offset < 0
+        {
+            return Err(format!("offset must not be negative, got {}", offset));
+        }
+
+        for (name, list, split_commas) in [
+            ("player", &mut self.player, false),
+            ("team", &mut self.team, true),
+            ("player_id", &mut self.player_id, true),
+        ] {
+            if let Some(values) = list.take() {
+                *list = Some(values.parsed(split_commas).map_err(|e| format!("Invalid {}: {}", name, e))?);
+            }
+        }
+
+        if let Some(sort_by) = self.sort.sort_by.take() {
+            self.sort.sort_by = Some(sort_by.normalized().map_err(|e| format!("Invalid sort_by: {}", e))?);
+        }
+        for filter in self.filters.iter_mut().flatten() {
+            filter.validate().map_err(|e| format!("Invalid where: {}", e))?;
+            filter.expr = filter.expr.clone().normalized();
+        }
+
+        if let Some(filter) = &mut self.filter {
+            filter.normalize().map_err(|e| format!("Invalid filter: {}", e))?;
+        }
+
+        if let Some(token) = self.cursor.as_deref() {
+            if self.offset.is_some() {
+                return Err("cursor and offset can't be combined".to_string());
+            }
+            let cursor = Cursor::decode(token)?;
+            if cursor.sort != self.sort.signature() {
+                return Err("cursor belongs to a different sort order; request the first page again without cursor".to_string());
+            }
+            self.page_cursor = Some(cursor);
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Serialize, ToSchema)]
 pub struct BoxScore {
     pub player_id: String,
@@ -199,6 +949,16 @@ pub struct BoxScore {
     pub fp: Option<f64>,
 }
 
+#[derive(Serialize, Clone, Default, ToSchema)]
+pub struct ResponseMeta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<PromptRef>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub llm: Option<LlmUsage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<Explanation>,
+}
+
 #[derive(Serialize, ToSchema)]
 pub struct PaginatedResponse {
     pub data: Vec<BoxScore>,
@@ -206,5 +966,208 @@ pub struct PaginatedResponse {
     pub limit: i64,
     pub offset: i64,
     pub explicit_limit: bool,
+    /// Cursor for the page after this one, absent on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    /// Cursor for the page before this one, absent on the first page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev_cursor: Option<String>,
     pub query_params: QueryParams,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<ResponseMeta>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn expr(value: Value) -> SortExpression {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn sum(terms: &[(SortField, f64)]) -> SortExpression {
+        SortExpression::Sum {
+            terms: terms
+                .iter()
+                .map(|(field, weight)| WeightedField { field: field.clone(), weight: *weight })
+                .collect(),
+        }
+    }
+
+    fn params(value: Value) -> Result<QueryParams, String> {
+        let mut params: QueryParams = serde_json::from_value(value).map_err(|e| e.to_string())?;
+        params.normalize()?;
+        Ok(params)
+    }
+
+    #[test]
+    fn validates_limit_and_offset() {
+        assert!(params(json!({"limit": 1, "offset": 0})).is_ok());
+        assert!(params(json!({"limit": 10_000})).is_ok());
+        for limit in [0, -5, 10_001, i64::MAX] {
+            let err = params(json!({ "limit": limit })).err().unwrap();
+            assert!(err.starts_with("limit must be between 1 and 10000"), "{}", err);
+        }
+        let err = params(json!({"offset": -1})).err().unwrap();
+        assert_eq!(err, "offset must not be negative, got -1");
+    }
+
+    #[test]
+    fn normalizes_season_ranges() {
+        let p = params(json!({"season_from": "2020", "season_to": "23-24"})).unwrap();
+        assert_eq!(p.season_from.as_deref(), Some("2019-20"));
+        assert_eq!(p.season_to.as_deref(), Some("2023-24"));
+        let err = params(json!({"season_from": "2023-24", "season_to": "2021"})).err().unwrap();
+        assert_eq!(err, "season_from (2023-24) is after season_to (2020-21)");
+    }
+
+    #[test]
+    fn rejects_non_finite_weights() {
+        for weight in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let err = sum(&[(SortField::Pts, weight)]).validate().unwrap_err();
+            assert!(err.contains("weight of pts must be a finite number"), "{}", err);
+        }
+        let err = SortExpression::Constant(f64::NAN).validate().unwrap_err();
+        assert!(err.contains("constant must be a finite number"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_empty_terms() {
+        let err = sum(&[]).validate().unwrap_err();
+        assert_eq!(err, "weighted sum needs at least one term");
+    }
+
+    #[test]
+    fn rejects_too_many_terms() {
+        let fields = [
+            SortField::Pts, SortField::Reb, SortField::Ast, SortField::Stl, SortField::Blk, SortField::Fgm,
+            SortField::Fga, SortField::Ftm, SortField::Fta, SortField::Oreb, SortField::Dreb,
+        ];
+        let terms: Vec<(SortField, f64)> = fields.into_iter().map(|field| (field, 1.0)).collect();
+        let err = sum(&terms).validate().unwrap_err();
+        assert!(err.contains("11 terms, at most 10"), "{}", err);
+
+        let err = expr(json!({"add": vec!["pts"; 11]})).validate().unwrap_err();
+        assert!(err.contains("add has 11 operands"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_duplicate_fields() {
+        let err = sum(&[(SortField::Pts, 1.0), (SortField::Reb, 1.0), (SortField::Pts, 2.0)])
+            .validate()
+            .unwrap_err();
+        assert!(err.contains("pts appears more than once"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_wrong_operand_counts() {
+        let err = expr(json!({"div": ["pts", "fga", "fta"]})).validate().unwrap_err();
+        assert_eq!(err, "div takes exactly 2 operands, got 3");
+        let err = expr(json!({"sub": ["pts"]})).validate().unwrap_err();
+        assert_eq!(err, "sub takes at least 2 operands, got 1");
+    }
+
+    #[test]
+    fn rejects_oversized_expressions() {
+        let mut nested = json!("pts");
+        for _ in 0..20 {
+            nested = json!({"add": [nested, 1.0]});
+        }
+        let err = expr(nested).validate().unwrap_err();
+        assert!(err.contains("at most 32 are allowed"), "{}", err);
+    }
+
+    #[test]
+    fn accepts_valid_expressions() {
+        assert!(sum(&[(SortField::Pts, 1.0), (SortField::Reb, 1.2)]).validate().is_ok());
+        assert!(expr(json!({"div": ["pts", {"add": ["fga", {"mul": [0.44, "fta"]}]}]})).validate().is_ok());
+        assert!(expr(json!("ts_percent")).validate().is_ok());
+    }
+
+    #[test]
+    fn equivalent_sums_produce_identical_sql() {
+        let a = sum(&[(SortField::Pts, 1.0), (SortField::Reb, 1.2), (SortField::Ast, 0.0)]).normalized();
+        let b = sum(&[(SortField::Reb, 1.2), (SortField::Pts, 1.0)]).normalized();
+        assert_eq!(a.as_sql(), b.as_sql());
+        assert_eq!(a.as_sql(), "((1 * pts) + (1.2 * reb))");
+    }
+
+    #[test]
+    fn unit_sum_reduces_to_field() {
+        assert_eq!(sum(&[(SortField::Pts, 1.0)]).normalized().as_sql(), "pts");
+        assert_eq!(sum(&[(SortField::Pts, 0.0)]).normalized().as_sql(), "0");
+    }
+
+    #[test]
+    fn commutative_operations_are_flattened_and_ordered() {
+        let a = expr(json!({"add": ["pts", {"add": ["reb", "ast"]}]})).normalized();
+        let b = expr(json!({"add": [{"add": ["ast", "pts"]}, "reb"]})).normalized();
+        assert_eq!(a.as_sql(), b.as_sql());
+        assert_eq!(a.as_sql(), "(ast + pts + reb)");
+
+        let a = expr(json!({"div": [{"mul": ["fga", 2]}, "min"]})).normalized();
+        let b = expr(json!({"div": [{"mul": [2, "fga"]}, "min"]})).normalized();
+        assert_eq!(a.as_sql(), b.as_sql());
+    }
+
+    #[test]
+    fn order_sensitive_operations_keep_their_order() {
+        let a = expr(json!({"sub": ["pts", "tov"]})).normalized();
+        let b = expr(json!({"sub": ["tov", "pts"]})).normalized();
+        assert_ne!(a.as_sql(), b.as_sql());
+    }
+
+    #[test]
+    fn normalize_reports_invalid_sort_and_where() {
+        let err = params(json!({"sort_by": {"terms": []}})).err().unwrap();
+        assert_eq!(err, "Invalid sort_by: weighted sum needs at least one term");
+
+        let err = params(json!({"sort_by": [{"by": "pts"}, {"by": {"div": ["pts"]}, "dir": "asc"}]})).err().unwrap();
+        assert_eq!(err, "Invalid sort_by: div takes exactly 2 operands, got 1");
+
+        let err = params(json!({"where": [{"expr": "pts"}]})).err().unwrap();
+        assert_eq!(err, "Invalid where: each condition needs a min or a max");
+
+        let err = params(json!({"where": [{"expr": "pts", "min": 30, "max": 20}]})).err().unwrap();
+        assert_eq!(err, "Invalid where: min (30) is greater than max (20)");
+    }
+
+    #[test]
+    fn normalize_canonicalizes_sort_and_where() {
+        let a = params(json!({
+            "sort_by": {"terms": [{"field": "reb", "weight": 1.2}, {"field": "pts", "weight": 1.0}]},
+            "where": [{"expr": {"mul": ["fta", 0.44]}, "min": 2}],
+        }))
+        .unwrap();
+        let b = params(json!({
+            "sort_by": {"terms": [{"field": "pts", "weight": 1.0}, {"field": "reb", "weight": 1.2}]},
+            "where": [{"expr": {"mul": [0.44, "fta"]}, "min": 2}],
+        }))
+        .unwrap();
+        assert_eq!(a.sort.signature(), b.sort.signature());
+        assert_eq!(
+            a.filters.unwrap()[0].as_sql(),
+            b.filters.unwrap()[0].as_sql()
+        );
+    }
+
+    #[test]
+    fn drops_repeated_list_values() {
+        let p = params(json!({
+            "player": ["LeBron James", "Kevin Durant", "LeBron James"],
+            "team": "LAL,BOS,LAL, BOS",
+            "player_id": "in:[\"2544\", \"2544\"]",
+        }))
+        .unwrap();
+        assert!(p.player == Some(ValueList::Many(vec!["LeBron James".into(), "Kevin Durant".into()])));
+        assert!(p.team == Some(ValueList::Many(vec!["LAL".into(), "BOS".into()])));
+        assert!(p.player_id == Some(ValueList::One("2544".into())));
+
+        // Repeats don't count towards the limit
+        let teams: Vec<String> = (0..MAX_LIST_VALUES * 2).map(|i| format!("T{}", i % MAX_LIST_VALUES)).collect();
+        assert!(params(json!({"team": teams})).is_ok());
+        let teams: Vec<String> = (0..=MAX_LIST_VALUES).map(|i| format!("T{}", i)).collect();
+        assert!(params(json!({"team": teams})).is_err());
+    }
 }
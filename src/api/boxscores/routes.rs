@@ -1,67 +1,119 @@
-use axum::{extract::Query, Json};
-use tokio_postgres::NoTls;
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::sync::Arc;
 
-use crate::api::db::query_boxscores;
-use super::models::{CountResponse, QueryParams, PaginatedResponse};
+use crate::api::db::{count_boxscores, export_sql, query_boxscores};
+use crate::api::export::{export_rows, ExportFormat, ExportSource, FormatParams};
+use crate::api::query::AppState;
+use super::models::{CountParams, CountResponse, QueryParams, PaginatedResponse};
 
 #[utoipa::path(
     get,
     path = "/api/boxscores/count",
+    params(QueryParams, CountParams),
     responses(
-        (status = 200, description = "Get total box score count", body = CountResponse),
+        (status = 200, description = "Number of box scores matching the filters, optionally per player, team or season", body = CountResponse),
+        (status = 400, description = "Invalid filter value"),
         (status = 500, description = "Internal server error")
     )
 )]
-pub async fn get_count() -> Result<Json<CountResponse>, String> {
-    let database_url = std::env::var("DATABASE_URL")
-        .map_err(|_| "DATABASE_URL not set".to_string())?;
+pub async fn get_count(
+    State(state): State<Arc<AppState>>,
+    Query(count): Query<CountParams>,
+    Query(mut params): Query<QueryParams>,
+) -> Result<Json<CountResponse>, (StatusCode, String)> {
+    params
+        .normalize()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
 
-    let (client, connection) = tokio_postgres::connect(&database_url, NoTls)
+    state
+        .player_resolver
+        .apply(&state.db_client, &mut params)
         .await
-        .map_err(|e| format!("Connection error: {}", e))?;
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
 
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("Connection error: {}", e);
-        }
-    });
-
-    let row = client
-        .query_one("SELECT COUNT(*) FROM player_box_scores", &[])
+    let response = count_boxscores(&state.db_client, &params, count.group_by)
         .await
-        .map_err(|e| format!("Query error: {}", e))?;
-
-    let count: i64 = row.get(0);
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
 
-    Ok(Json(CountResponse { count }))
+    Ok(Json(response))
 }
 
 #[utoipa::path(
     get,
     path = "/api/boxscores",
-    params(QueryParams),
+    params(QueryParams, FormatParams),
     responses(
         (status = 200, description = "Get box scores with optional filters, sorting, and pagination", body = PaginatedResponse),
+        (status = 200, description = "Every matching box score as CSV (Accept: text/csv or format=csv)", content_type = "text/csv"),
+        (status = 200, description = "Every matching box score as NDJSON (Accept: application/x-ndjson or format=ndjson)", content_type = "application/x-ndjson"),
+        (status = 200, description = "Every matching box score as an Arrow IPC stream (format=arrow)", content_type = "application/vnd.apache.arrow.stream"),
+        (status = 200, description = "Every matching box score as a Parquet file (format=parquet)", content_type = "application/vnd.apache.parquet"),
+        (status = 400, description = "Invalid filter value or format"),
         (status = 500, description = "Internal server error")
     )
 )]
 pub async fn get_boxscores(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(format): Query<FormatParams>,
     Query(params): Query<QueryParams>,
-) -> Result<Json<PaginatedResponse>, String> {
-    let database_url = std::env::var("DATABASE_URL")
-        .map_err(|_| "DATABASE_URL not set".to_string())?;
+) -> Result<Response, (StatusCode, String)> {
+    let format = ExportFormat::negotiate(&headers, format.format.as_deref())?;
+    list_boxscores(&state, params, format).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/boxscores/search",
+    params(FormatParams),
+    request_body(content = QueryParams, description = "The same parameters as GET /api/boxscores as a JSON object, where `filter` can nest and/or/not groups"),
+    responses(
+        (status = 200, description = "Box scores matching the body, with sorting and pagination", body = PaginatedResponse),
+        (status = 200, description = "Every matching box score as CSV (Accept: text/csv or format=csv)", content_type = "text/csv"),
+        (status = 200, description = "Every matching box score as NDJSON (Accept: application/x-ndjson or format=ndjson)", content_type = "application/x-ndjson"),
+        (status = 200, description = "Every matching box score as an Arrow IPC stream (format=arrow)", content_type = "application/vnd.apache.arrow.stream"),
+        (status = 200, description = "Every matching box score as a Parquet file (format=parquet)", content_type = "application/vnd.apache.parquet"),
+        (status = 400, description = "Invalid filter, value or format"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn search_boxscores(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(format): Query<FormatParams>,
+    Json(params): Json<QueryParams>,
+) -> Result<Response, (StatusCode, String)> {
+    let format = ExportFormat::negotiate(&headers, format.format.as_deref())?;
+    list_boxscores(&state, params, format).await
+}
 
-    let (client, connection) = tokio_postgres::connect(&database_url, NoTls)
+async fn list_boxscores(
+    state: &AppState,
+    mut params: QueryParams,
+    format: ExportFormat,
+) -> Result<Response, (StatusCode, String)> {
+    params
+        .normalize()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    state
+        .player_resolver
+        .apply(&state.db_client, &mut params)
         .await
-        .map_err(|e| format!("Connection error: {}", e))?;
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
 
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("Connection error: {}", e);
-        }
-    });
+    if format != ExportFormat::Json {
+        return export_rows(ExportSource::Primary, export_sql(&params), format, "boxscores").await;
+    }
 
-    let response = query_boxscores(&client, params).await?;
+    let response = query_boxscores(&state.db_client, params)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
 
-    Ok(Json(response))
+    Ok(Json(response).into_response())
 }
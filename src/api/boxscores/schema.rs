@@ -0,0 +1,223 @@
+use schemars::schema_for;
+use serde_json::{json, Map, Value};
+
+use super::models::QueryParams;
+
+// JSON schema of QueryParams as the LLMs get it: references inlined, optional fields
+// reduced to their plain type and without the keywords the providers reject
+pub fn query_params_schema() -> Value {
+    let root = serde_json::to_value(schema_for!(QueryParams)).unwrap_or_default();
+    let defs = root.get("$defs").cloned().unwrap_or_default();
+    simplify(&root, &defs, &[])
+}
+
+// Schema for /api/query: the filter parameters plus the fields the model uses to explain
+// itself or ask for clarification
+pub fn llm_query_schema() -> Value {
+    let mut schema = query_params_schema();
+    if let Some(properties) = schema.get_mut("properties").and_then(Value::as_object_mut) {
+        properties.insert(
+            "reasoning".to_string(),
+            json!({"type": "string", "description": "Explain your reasoning for extracting these parameters from the query"}),
+        );
+        properties.insert(
+            "needs_clarification".to_string(),
+            json!({"type": "boolean", "description": "True if the player reference is ambiguous and the user should pick one of the candidates"}),
+        );
+        properties.insert(
+            "clarification_question".to_string(),
+            json!({"type": "string", "description": "Question to ask the user when needs_clarification is true"}),
+        );
+        properties.insert(
+            "candidates".to_string(),
+            json!({
+                "type": "array",
+                "description": "Full names of every player the query could refer to",
+                "items": {"type": "string"}
+            }),
+        );
+    }
+    schema["required"] = json!(["reasoning"]);
+    schema
+}
+
+// One line per QueryParams field, e.g. "- pts (integer): Minimum points", for the prompts
+pub fn field_docs() -> String {
+    let schema = query_params_schema();
+    let Some(properties) = schema.get("properties").and_then(Value::as_object) else {
+        return String::new();
+    };
+
+    properties
+        .iter()
+        .map(|(name, property)| {
+            let description = property.get("description").and_then(Value::as_str).unwrap_or("");
+            let mut line = format!("- {} ({}): {}", name, type_name(property), description);
+            if let Some(values) = property.get("enum").and_then(Value::as_array) {
+                let values: Vec<&str> = values.iter().filter_map(Value::as_str).collect();
+                line.push_str(&format!(" [one of: {}]", values.join(", ")));
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn type_name(property: &Value) -> String {
+    if let Some(t) = property.get("type").and_then(Value::as_str) {
+        return t.to_string();
+    }
+    match property.get("anyOf").and_then(Value::as_array) {
+        Some(variants) => {
+            let mut names: Vec<String> = Vec::new();
+            for name in variants.iter().map(type_name) {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+            names.join(" or ")
+        }
+        None => "any".to_string(),
+    }
+}
+
+fn simplify(schema: &Value, defs: &Value, expanding: &[&str]) -> Value {
+    match schema {
+        Value::Object(object) => {
+            if let Some(name) = object
+                .get("$ref")
+                .and_then(Value::as_str)
+                .and_then(|r| r.strip_prefix("#/$defs/"))
+            {
+                // Expressions and filter groups contain themselves; the nested occurrence is
+                // replaced by the type's description
+                if expanding.contains(&name) {
+                    let description = defs[name].get("description").and_then(Value::as_str).unwrap_or_default();
+                    return json!({"description": format!("Nested value of the same form. {}", description)});
+                }
+                let expanding: Vec<&str> = expanding.iter().copied().chain([name]).collect();
+                let mut resolved = simplify(&defs[name], defs, &expanding);
+                // Keep the field's own description over the referenced type's
+                if let (Some(description), Some(target)) = (object.get("description"), resolved.as_object_mut()) {
+                    target.insert("description".to_string(), description.clone());
+                }
+                return resolved;
+            }
+
+            let mut out = Map::new();
+            for (key, value) in object {
+                match key.as_str() {
+                    "$schema" | "$defs" | "title" | "format" | "minimum" | "maximum" | "default" => {}
+                    "type" => {
+                        // Option<T> is ["T", "null"]; absent fields already mean "no filter"
+                        let types: Vec<&Value> = match value {
+                            Value::Array(types) => types.iter().filter(|t| t.as_str() != Some("null")).collect(),
+                            other => vec![other],
+                        };
+                        if let [single] = types.as_slice() {
+                            out.insert(key.clone(), (*single).clone());
+                        } else {
+                            out.insert(key.clone(), Value::Array(types.into_iter().cloned().collect()));
+                        }
+                    }
+                    "anyOf" | "oneOf" => {
+                        let variants: Vec<Value> = value
+                            .as_array()
+                            .into_iter()
+                            .flatten()
+                            .filter(|v| v.get("type").and_then(Value::as_str) != Some("null"))
+                            .map(|v| simplify(v, defs, expanding))
+                            // An untagged enum inside another one is a nested anyOf; lift its variants
+                            .flat_map(|v| match v.get("anyOf").and_then(Value::as_array) {
+                                Some(inner) if v.as_object().is_some_and(|o| o.len() == 1) => inner.clone(),
+                                _ => vec![v],
+                            })
+                            .collect();
+                        if let [single] = variants.as_slice() {
+                            if let Value::Object(inner) = single {
+                                for (k, v) in inner {
+                                    out.entry(k.clone()).or_insert_with(|| v.clone());
+                                }
+                            }
+                        } else {
+                            out.insert("anyOf".to_string(), Value::Array(variants));
+                        }
+                    }
+                    _ => {
+                        out.insert(key.clone(), simplify(value, defs, expanding));
+                    }
+                }
+            }
+            Value::Object(out)
+        }
+        Value::Array(items) => Value::Array(items.iter().map(|v| simplify(v, defs, expanding)).collect()),
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::boxscores::models::SortParams;
+    use utoipa::IntoParams;
+
+    // Every query parameter of QueryParams, from the OpenAPI derive rather than schemars, with
+    // the flattened sort fields taken from their serde form. The cursor is for paging clients
+    // and is kept out of the LLM schemas on purpose.
+    fn query_param_names() -> Vec<String> {
+        let sort = serde_json::to_value(SortParams::default()).unwrap();
+        QueryParams::into_params(|| None)
+            .into_iter()
+            .map(|param| param.name)
+            .filter(|name| name != "cursor")
+            .flat_map(|name| match name.as_str() {
+                "sort" => sort.as_object().unwrap().keys().cloned().collect(),
+                _ => vec![name],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn covers_every_query_param() {
+        let names = query_param_names();
+        for name in ["tov", "pf", "plus_minus", "fp", "offset", "limit", "filter", "where", "season_type", "sort_by"] {
+            assert!(names.iter().any(|n| n == name), "{} is not a query parameter", name);
+        }
+
+        let docs = field_docs();
+        for schema in [query_params_schema(), llm_query_schema()] {
+            let properties = schema["properties"].as_object().unwrap();
+            for name in &names {
+                let description = properties
+                    .get(name)
+                    .and_then(|p| p.get("description"))
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                assert!(!description.is_empty(), "{} has no description in the LLM schema", name);
+            }
+        }
+        for name in &names {
+            let line = docs
+                .lines()
+                .find(|line| line.starts_with(&format!("- {} (", name)))
+                .unwrap_or_else(|| panic!("{} is missing from the prompt field docs", name));
+            assert!(!line.ends_with(": "), "{} has no description in the prompt field docs", name);
+        }
+
+        let properties = query_params_schema()["properties"].as_object().unwrap().clone();
+        let mut documented: Vec<&String> = properties.keys().collect();
+        let mut expected: Vec<&String> = names.iter().collect();
+        documented.sort();
+        expected.sort();
+        assert_eq!(documented, expected);
+    }
+
+    #[test]
+    fn llm_schema_adds_explanation_fields() {
+        let schema = llm_query_schema();
+        for name in ["reasoning", "needs_clarification", "clarification_question", "candidates"] {
+            assert!(schema["properties"].get(name).is_some(), "{}", name);
+        }
+        assert_eq!(schema["required"], json!(["reasoning"]));
+    }
+}
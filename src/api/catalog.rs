@@ -0,0 +1,263 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tokio_postgres::Client;
+
+use super::query::AppState;
+use super::usage::check_admin;
+
+// Views the LLM-generated SQL may read from
+pub const SQL_VIEWS: &[&str] = &["player_box_scores_view"];
+
+// Low-cardinality columns whose values are listed in the prompt
+const SAMPLED_COLUMNS: &[&str] = &["team", "season", "w_l"];
+const MAX_SAMPLES: usize = 100;
+
+// Used for columns without a comment in the database
+const COLUMN_NOTES: &[(&str, &str)] = &[
+    ("min", "minutes played"),
+    ("pts", "points"),
+    ("fgm", "field goals made"),
+    ("fga", "field goals attempted"),
+    ("fg_percent", "field goal percentage, stored as percentage e.g. 45.5 not 0.455"),
+    ("three_pm", "3-pointers made"),
+    ("three_pa", "3-pointers attempted"),
+    ("three_p_percent", "three point percentage, stored as percentage e.g. 38.2 not 0.382"),
+    ("ftm", "free throws made"),
+    ("fta", "free throws attempted"),
+    ("ft_percent", "free throw percentage, stored as percentage e.g. 87.5 not 0.875"),
+    ("oreb", "offensive rebounds"),
+    ("dreb", "defensive rebounds"),
+    ("reb", "total rebounds"),
+    ("ast", "assists"),
+    ("stl", "steals"),
+    ("blk", "blocks"),
+    ("tov", "turnovers"),
+    ("pf", "personal fouls"),
+    ("fp", "fantasy points"),
+];
+
+// Schema text used until introspection succeeds
+const STATIC_SCHEMA: &str = "View player_box_scores_view:
+- player_id VARCHAR(50)
+- game_id VARCHAR(50)
+- team_id VARCHAR(50)
+- season VARCHAR(20)
+- player VARCHAR(255)
+- team VARCHAR(100)
+- match_up VARCHAR(100)
+- game_date VARCHAR(50)
+- w_l VARCHAR(10)
+- min INTEGER (minutes played)
+- pts INTEGER (points)
+- fgm INTEGER (field goals made)
+- fga INTEGER (field goals attempted)
+- fg_percent DOUBLE PRECISION (field goal percentage, stored as percentage e.g. 45.5 not 0.455)
+- three_pm INTEGER (3-pointers made)
+- three_pa INTEGER (3-pointers attempted)
+- three_p_percent DOUBLE PRECISION (three point percentage, stored as percentage e.g. 38.2 not 0.382)
+- ftm INTEGER (free throws made)
+- fta INTEGER (free throws attempted)
+- ft_percent DOUBLE PRECISION (free throw percentage, stored as percentage e.g. 87.5 not 0.875)
+- oreb INTEGER (offensive rebounds)
+- dreb INTEGER (defensive rebounds)
+- reb INTEGER (total rebounds)
+- ast INTEGER (assists)
+- stl INTEGER (steals)
+- blk INTEGER (blocks)
+- tov INTEGER (turnovers)
+- pf INTEGER (personal fouls)
+- plus_minus INTEGER
+- fp DOUBLE PRECISION (fantasy points)";
+
+#[derive(Serialize, Clone)]
+pub struct ColumnInfo {
+    pub name: String,
+    pub data_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sample_values: Vec<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ViewInfo {
+    pub name: String,
+    pub columns: Vec<ColumnInfo>,
+}
+
+#[derive(Serialize)]
+pub struct SchemaSnapshot {
+    pub introspected: bool,
+    pub views: Vec<ViewInfo>,
+    pub prompt_text: String,
+}
+
+// Column list of the whitelisted views as rendered into the SQL prompt. Starts out with
+// the static description and is replaced by refresh(), at startup and on /api/schema/refresh.
+#[derive(Default)]
+pub struct SchemaCatalog {
+    views: RwLock<Option<Vec<ViewInfo>>>,
+}
+
+impl SchemaCatalog {
+    pub async fn refresh(&self, client: &Client) -> Result<(), String> {
+        let mut views = Vec::new();
+        for view in SQL_VIEWS {
+            views.push(introspect_view(client, view).await?);
+        }
+
+        let columns: usize = views.iter().map(|v| v.columns.len()).sum();
+        println!("schema catalog refreshed: {} views, {} columns", views.len(), columns);
+
+        *self.views.write().await = Some(views);
+        Ok(())
+    }
+
+    pub async fn prompt_text(&self) -> String {
+        match self.views.read().await.as_ref() {
+            Some(views) => render(views),
+            None => STATIC_SCHEMA.to_string(),
+        }
+    }
+
+    pub async fn snapshot(&self) -> SchemaSnapshot {
+        let views = self.views.read().await.clone();
+        let prompt_text = match views.as_ref() {
+            Some(views) => render(views),
+            None => STATIC_SCHEMA.to_string(),
+        };
+        SchemaSnapshot {
+            introspected: views.is_some(),
+            views: views.unwrap_or_default(),
+            prompt_text,
+        }
+    }
+}
+
+async fn introspect_view(client: &Client, view: &str) -> Result<ViewInfo, String> {
+    let rows = client
+        .query(
+            "SELECT c.column_name::text, c.data_type::text, c.character_maximum_length::int4,
+                    col_description(format('%I.%I', c.table_schema, c.table_name)::regclass, c.ordinal_position::int4)
+             FROM information_schema.columns c
+             WHERE c.table_name = $1 AND c.table_schema = current_schema()
+             ORDER BY c.ordinal_position",
+            &[&view],
+        )
+        .await
+        .map_err(|e| format!("Schema introspection error for {}: {}", view, e))?;
+
+    if rows.is_empty() {
+        return Err(format!("Schema introspection found no columns for {}", view));
+    }
+
+    let mut columns = Vec::new();
+    for row in &rows {
+        let name: String = row.get(0);
+        let data_type: String = row.get(1);
+        let length: Option<i32> = row.get(2);
+        let comment: Option<String> = row.get(3);
+
+        let data_type = match (data_type.as_str(), length) {
+            ("character varying", Some(n)) => format!("VARCHAR({})", n),
+            ("character varying", None) => "VARCHAR".to_string(),
+            ("character", Some(n)) => format!("CHAR({})", n),
+            (other, _) => other.to_uppercase(),
+        };
+
+        let comment = comment.filter(|c| !c.trim().is_empty()).or_else(|| {
+            COLUMN_NOTES
+                .iter()
+                .find(|(column, _)| *column == name)
+                .map(|(_, note)| note.to_string())
+        });
+
+        let (sample_values, truncated) = if SAMPLED_COLUMNS.contains(&name.as_str()) {
+            sample_values(client, view, &name).await?
+        } else {
+            (Vec::new(), false)
+        };
+
+        columns.push(ColumnInfo {
+            name,
+            data_type,
+            comment,
+            sample_values,
+            truncated,
+        });
+    }
+
+    Ok(ViewInfo {
+        name: view.to_string(),
+        columns,
+    })
+}
+
+async fn sample_values(client: &Client, view: &str, column: &str) -> Result<(Vec<String>, bool), String> {
+    let query = format!(
+        "SELECT DISTINCT \"{column}\"::text FROM \"{view}\" WHERE \"{column}\" IS NOT NULL ORDER BY 1 LIMIT {}",
+        MAX_SAMPLES + 1
+    );
+    let rows = client
+        .query(&query, &[])
+        .await
+        .map_err(|e| format!("Sample values query error for {}.{}: {}", view, column, e))?;
+
+    let mut values: Vec<String> = rows.iter().map(|row| row.get(0)).collect();
+    let truncated = values.len() > MAX_SAMPLES;
+    values.truncate(MAX_SAMPLES);
+    Ok((values, truncated))
+}
+
+fn render(views: &[ViewInfo]) -> String {
+    views
+        .iter()
+        .map(|view| {
+            let mut lines = vec![format!("View {}:", view.name)];
+            for column in &view.columns {
+                let mut line = format!("- {} {}", column.name, column.data_type);
+                if let Some(ref comment) = column.comment {
+                    line.push_str(&format!(" ({})", comment));
+                }
+                if !column.sample_values.is_empty() {
+                    line.push_str(&format!(
+                        ", values: {}{}",
+                        column.sample_values.join(", "),
+                        if column.truncated { ", ..." } else { "" }
+                    ));
+                }
+                lines.push(line);
+            }
+            lines.join("\n")
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+pub async fn get_schema(State(state): State<Arc<AppState>>) -> Json<SchemaSnapshot> {
+    Json(state.schema_catalog.snapshot().await)
+}
+
+// Re-introspects the database and rewrites the SQL prompt, so only admins may trigger it
+pub async fn post_schema_refresh(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<SchemaSnapshot>, (StatusCode, String)> {
+    check_admin(&headers)?;
+
+    state
+        .schema_catalog
+        .refresh(&state.readonly_db_client)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    Ok(Json(state.schema_catalog.snapshot().await))
+}
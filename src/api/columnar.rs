@@ -0,0 +1,111 @@
+use arrow_array::{ArrayRef, BooleanArray, Float64Array, Int32Array, Int64Array, RecordBatch, StringArray};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use serde_json::Value;
+use std::sync::Arc;
+use tokio_postgres::{Column, Row};
+
+use super::sql::column_value;
+
+// Rows per record batch
+pub const BATCH_ROWS: usize = 8192;
+
+// Rows per Parquet row group. Each finished row group is sent to the client right away.
+const ROW_GROUP_ROWS: usize = 65_536;
+
+// Arrow schema for a result set, from the Postgres column types. Every column is nullable,
+// and types column_value doesn't read (dates, numerics, ...) become null strings.
+pub fn arrow_schema(columns: &[Column]) -> SchemaRef {
+    let fields: Vec<Field> = columns
+        .iter()
+        .map(|column| {
+            let data_type = match column.type_().name() {
+                "int4" => DataType::Int32,
+                "int8" => DataType::Int64,
+                "float4" | "float8" => DataType::Float64,
+                "bool" => DataType::Boolean,
+                _ => DataType::Utf8,
+            };
+            Field::new(column.name(), data_type, true)
+        })
+        .collect();
+    Arc::new(Schema::new(fields))
+}
+
+pub fn record_batch(schema: &SchemaRef, rows: &[Row]) -> Result<RecordBatch, String> {
+    let columns: Vec<ArrayRef> = schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(idx, field)| {
+            let values = rows.iter().map(|row| column_value(row, idx));
+            let array: ArrayRef = match field.data_type() {
+                DataType::Int32 => Arc::new(Int32Array::from_iter(values.map(|v| v.as_i64().map(|n| n as i32)))),
+                DataType::Int64 => Arc::new(Int64Array::from_iter(values.map(|v| v.as_i64()))),
+                DataType::Float64 => Arc::new(Float64Array::from_iter(values.map(|v| v.as_f64()))),
+                DataType::Boolean => Arc::new(BooleanArray::from_iter(values.map(|v| v.as_bool()))),
+                _ => Arc::new(StringArray::from_iter(values.map(|v| match v {
+                    Value::Null => None,
+                    Value::String(s) => Some(s),
+                    other => Some(other.to_string()),
+                }))),
+            };
+            array
+        })
+        .collect();
+
+    RecordBatch::try_new(schema.clone(), columns).map_err(|e| format!("Failed to build record batch: {}", e))
+}
+
+// Encodes record batches incrementally. Each call returns the bytes that are ready to be
+// sent, so the response can be streamed while later batches are still being read.
+pub enum BatchWriter {
+    Arrow(StreamWriter<Vec<u8>>),
+    Parquet(ArrowWriter<Vec<u8>>),
+}
+
+impl BatchWriter {
+    pub fn arrow(schema: &SchemaRef) -> Result<Self, String> {
+        StreamWriter::try_new(Vec::new(), schema)
+            .map(BatchWriter::Arrow)
+            .map_err(|e| format!("Failed to start Arrow stream: {}", e))
+    }
+
+    pub fn parquet(schema: &SchemaRef) -> Result<Self, String> {
+        let properties = WriterProperties::builder()
+            .set_max_row_group_size(ROW_GROUP_ROWS)
+            .build();
+        ArrowWriter::try_new(Vec::new(), schema.clone(), Some(properties))
+            .map(BatchWriter::Parquet)
+            .map_err(|e| format!("Failed to start Parquet file: {}", e))
+    }
+
+    pub fn write(&mut self, batch: &RecordBatch) -> Result<Vec<u8>, String> {
+        match self {
+            BatchWriter::Arrow(writer) => {
+                writer.write(batch).map_err(|e| format!("Arrow write failed: {}", e))?;
+                Ok(std::mem::take(writer.get_mut()))
+            }
+            BatchWriter::Parquet(writer) => {
+                writer.write(batch).map_err(|e| format!("Parquet write failed: {}", e))?;
+                Ok(std::mem::take(writer.inner_mut()))
+            }
+        }
+    }
+
+    // Writes the end-of-stream marker or the Parquet footer
+    pub fn finish(mut self) -> Result<Vec<u8>, String> {
+        match &mut self {
+            BatchWriter::Arrow(writer) => {
+                writer.finish().map_err(|e| format!("Arrow write failed: {}", e))?;
+                Ok(std::mem::take(writer.get_mut()))
+            }
+            BatchWriter::Parquet(writer) => {
+                writer.finish().map_err(|e| format!("Parquet write failed: {}", e))?;
+                Ok(std::mem::take(writer.inner_mut()))
+            }
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use super::boxscores::models::SortOrder;
+
+// Position of a row in a sorted box score listing. Sort values are kept as Postgres text
+// so they compare against the sort expressions the same way whatever their type.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Cursor {
+    // Sort the cursor was created for, so a token can't be replayed against another order
+    #[serde(rename = "s")]
+    pub sort: String,
+    #[serde(rename = "k")]
+    pub keys: Vec<Option<String>>,
+    #[serde(rename = "g")]
+    pub game_id: String,
+    #[serde(rename = "p")]
+    pub player_id: String,
+    // Page backwards, towards the rows before this one
+    #[serde(rename = "b", default)]
+    pub backward: bool,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    pub fn decode(token: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid cursor '{}': use next_cursor or prev_cursor from a previous response", token);
+        let bytes = URL_SAFE_NO_PAD.decode(token.trim()).map_err(|_| invalid())?;
+        serde_json::from_slice(&bytes).map_err(|_| invalid())
+    }
+
+    // Rows after this one in the listing order (or before it when paging backwards): those
+    // that sort after it on the first key they differ on, with game_id and player_id breaking
+    // any remaining tie.
+    pub fn where_sql(&self, orders: &[SortOrder], ids_asc: bool) -> String {
+        let mut branches = Vec::new();
+        let mut equal: Vec<String> = Vec::new();
+        for (order, key) in orders.iter().zip(&self.keys) {
+            // Reading backwards flips both the direction and where NULLs sit
+            let asc = order.asc != self.backward;
+            let nulls_first = order.nulls_first != self.backward;
+            let after = match key {
+                Some(key) => {
+                    let key = key.replace("'", "''");
+                    let op = if asc { ">" } else { "<" };
+                    if nulls_first {
+                        Some(format!("{} {} '{}'", order.sql, op, key))
+                    } else {
+                        Some(format!("({} {} '{}' OR {} IS NULL)", order.sql, op, key, order.sql))
+                    }
+                }
+                None if nulls_first => Some(format!("{} IS NOT NULL", order.sql)),
+                None => None,
+            };
+            if let Some(after) = after {
+                branches.push(equal.iter().cloned().chain([after]).collect::<Vec<_>>().join(" AND "));
+            }
+            equal.push(match key {
+                Some(key) => format!("{} = '{}'", order.sql, key.replace("'", "''")),
+                None => format!("{} IS NULL", order.sql),
+            });
+        }
+
+        let op = if ids_asc != self.backward { ">" } else { "<" };
+        equal.push(format!(
+            "(game_id, player_id) {} ('{}', '{}')",
+            op,
+            self.game_id.replace("'", "''"),
+            self.player_id.replace("'", "''")
+        ));
+        branches.push(equal.join(" AND "));
+
+        let branches: Vec<String> = branches.iter().map(|branch| format!("({})", branch)).collect();
+        format!(" AND ({})", branches.join(" OR "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(sql: &str, asc: bool, nulls_first: bool) -> SortOrder {
+        SortOrder { sql: sql.to_string(), asc, nulls_first }
+    }
+
+    fn cursor(keys: &[Option<&str>], backward: bool) -> Cursor {
+        Cursor {
+            sort: String::new(),
+            keys: keys.iter().map(|k| k.map(str::to_string)).collect(),
+            game_id: "g1".to_string(),
+            player_id: "p1".to_string(),
+            backward,
+        }
+    }
+
+    #[test]
+    fn ascending_key_with_nulls_last() {
+        let sql = cursor(&[Some("30")], false).where_sql(&[order("pts", true, false)], true);
+        assert_eq!(
+            sql,
+            " AND (((pts > '30' OR pts IS NULL)) OR (pts = '30' AND (game_id, player_id) > ('g1', 'p1')))"
+        );
+    }
+
+    #[test]
+    fn descending_key_with_nulls_first() {
+        let sql = cursor(&[Some("30")], false).where_sql(&[order("pts", false, true)], false);
+        assert_eq!(sql, " AND ((pts < '30') OR (pts = '30' AND (game_id, player_id) < ('g1', 'p1')))");
+    }
+
+    #[test]
+    fn null_key_sorted_last_only_continues_within_the_nulls() {
+        let sql = cursor(&[None], false).where_sql(&[order("pts", true, false)], true);
+        assert_eq!(sql, " AND ((pts IS NULL AND (game_id, player_id) > ('g1', 'p1')))");
+    }
+
+    #[test]
+    fn null_key_sorted_first_continues_into_the_values() {
+        let sql = cursor(&[None], false).where_sql(&[order("pts", true, true)], true);
+        assert_eq!(
+            sql,
+            " AND ((pts IS NOT NULL) OR (pts IS NULL AND (game_id, player_id) > ('g1', 'p1')))"
+        );
+    }
+
+    #[test]
+    fn backward_cursor_flips_direction_and_null_placement() {
+        let sql = cursor(&[Some("30")], true).where_sql(&[order("pts", true, false)], true);
+        assert_eq!(sql, " AND ((pts < '30') OR (pts = '30' AND (game_id, player_id) < ('g1', 'p1')))");
+
+        let sql = cursor(&[None], true).where_sql(&[order("pts", true, false)], true);
+        assert_eq!(
+            sql,
+            " AND ((pts IS NOT NULL) OR (pts IS NULL AND (game_id, player_id) < ('g1', 'p1')))"
+        );
+    }
+
+    #[test]
+    fn later_keys_only_apply_when_earlier_keys_are_equal() {
+        let orders = [order("pts", false, false), order("ast", true, false)];
+        let sql = cursor(&[Some("30"), None], false).where_sql(&orders, false);
+        assert_eq!(
+            sql,
+            " AND (((pts < '30' OR pts IS NULL)) OR (pts = '30' AND ast IS NULL AND (game_id, player_id) < ('g1', 'p1')))"
+        );
+    }
+
+    #[test]
+    fn escapes_quotes_in_keys_and_ids() {
+        let mut c = cursor(&[Some("O'Neal")], false);
+        c.game_id = "g'1".to_string();
+        let sql = c.where_sql(&[order("player", true, true)], true);
+        assert_eq!(
+            sql,
+            " AND ((player > 'O''Neal') OR (player = 'O''Neal' AND (game_id, player_id) > ('g''1', 'p1')))"
+        );
+    }
+
+    #[test]
+    fn round_trips_and_rejects_garbage_tokens() {
+        let c = cursor(&[Some("30"), None], true);
+        let decoded = Cursor::decode(&c.encode()).unwrap();
+        assert_eq!(decoded.keys, c.keys);
+        assert_eq!(decoded.game_id, "g1");
+        assert!(decoded.backward);
+
+        for token in ["", "not base64!", "bm90IGpzb24"] {
+            let err = Cursor::decode(token).err().unwrap();
+            assert!(err.starts_with("Invalid cursor"), "{}", err);
+        }
+    }
+}
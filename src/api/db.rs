@@ -1,12 +1,35 @@
+use tokio_postgres::types::ToSql;
 use tokio_postgres::Client;
 
-use super::boxscores::models::{BoxScore, QueryParams, PaginatedResponse};
+use super::boxscores::filters::SqlParam;
+use super::boxscores::models::{BoxScore, CountGroup, CountResponse, GroupCount, QueryParams, PaginatedResponse};
+use super::cursor::Cursor;
+use super::teams::team_sql_list;
 
-pub async fn query_boxscores(
-    client: &Client,
-    params: QueryParams,
-) -> Result<PaginatedResponse, String> {
-    let mut query = String::from("SELECT player_id, game_id, team_id, season, player, team, match_up, game_date, w_l, min, pts, fgm, fga, fg_percent, three_pm, three_pa, three_p_percent, ftm, fta, ft_percent, oreb, dreb, reb, ast, stl, blk, tov, pf, plus_minus, fp FROM player_box_scores WHERE 1=1");
+const BOX_SCORE_COLUMNS: &str = "player_id, game_id, team_id, season, player, team, match_up, game_date, w_l, min, pts, fgm, fga, fg_percent, three_pm, three_pa, three_p_percent, ftm, fta, ft_percent, oreb, dreb, reb, ast, stl, blk, tov, pf, plus_minus, fp";
+
+// SQL text and the values for its $n placeholders
+pub struct BoundSql {
+    pub sql: String,
+    pub params: Vec<SqlParam>,
+}
+
+impl From<&str> for BoundSql {
+    fn from(sql: &str) -> Self {
+        BoundSql { sql: sql.to_string(), params: Vec::new() }
+    }
+}
+
+impl BoundSql {
+    pub fn refs(&self) -> Vec<&(dyn ToSql + Sync)> {
+        self.params.iter().map(|p| p.as_ref() as &(dyn ToSql + Sync)).collect()
+    }
+}
+
+// WHERE clause for the filters in params. Values of the filter tree are bound, and added
+// to values.
+pub fn where_sql(params: &QueryParams, values: &mut Vec<SqlParam>) -> String {
+    let mut query = String::from(" WHERE 1=1");
 
     if let Some(pts) = params.pts {
         query.push_str(&format!(" AND pts >= {}", pts));
@@ -72,29 +95,213 @@ pub async fn query_boxscores(
         query.push_str(&format!(" AND min >= {}", min));
     }
 
+    for filter in params.filters.iter().flatten() {
+        query.push_str(&filter.as_sql());
+    }
+
     if let Some(ref season) = params.season {
         query.push_str(&format!(" AND season = '{}'", season.replace("'", "''")));
     }
-    if let Some(ref player) = params.player {
-        query.push_str(&format!(" AND player ILIKE '%{}%'", player.replace("'", "''")));
+    if let Some(ref season_from) = params.season_from {
+        query.push_str(&format!(" AND season >= '{}'", season_from.replace("'", "''")));
+    }
+    if let Some(ref season_to) = params.season_to {
+        query.push_str(&format!(" AND season <= '{}'", season_to.replace("'", "''")));
+    }
+    if let Some(season_type) = params.season_type {
+        query.push_str(&format!(" AND game_id LIKE '{}%'", season_type.game_id_prefix()));
+    }
+    if let Some(ref player_ids) = params.resolved_player_ids {
+        let ids: Vec<String> = player_ids
+            .iter()
+            .map(|id| format!("'{}'", id.replace("'", "''")))
+            .collect();
+        query.push_str(&format!(" AND player_id IN ({})", ids.join(", ")));
+    } else if let Some(ref player) = params.player {
+        let names: Vec<String> = player
+            .values()
+            .iter()
+            .map(|name| format!("player ILIKE '%{}%'", name.replace("'", "''")))
+            .collect();
+        query.push_str(&format!(" AND ({})", names.join(" OR ")));
     }
     if let Some(ref team) = params.team {
-        query.push_str(&format!(" AND team = '{}'", team.replace("'", "''")));
+        let teams: Vec<String> = team
+            .values()
+            .iter()
+            .map(|name| team_sql_list(name, params.season.as_deref()))
+            .collect();
+        query.push_str(&format!(" AND team IN ({})", teams.join(", ")));
+    }
+    if let Some(ref opponent) = params.opponent {
+        // match_up is "LAL vs. BOS" at home and "LAL @ BOS" away
+        query.push_str(&format!(" AND split_part(match_up, ' ', 3) IN ({})", team_sql_list(opponent, params.season.as_deref())));
     }
     if let Some(ref player_id) = params.player_id {
-        query.push_str(&format!(" AND player_id = '{}'", player_id.replace("'", "''")));
+        let ids: Vec<String> = player_id
+            .values()
+            .iter()
+            .map(|id| format!("'{}'", id.replace("'", "''")))
+            .collect();
+        query.push_str(&format!(" AND player_id IN ({})", ids.join(", ")));
     }
     if let Some(ref game_id) = params.game_id {
         query.push_str(&format!(" AND game_id = '{}'", game_id.replace("'", "''")));
     }
+    if let Some(ref filter) = params.filter {
+        query.push_str(&format!(" AND ({})", filter.to_sql(params.season.as_deref(), values)));
+    }
+
+    query
+}
+
+// Orders by the sort keys, then by game_id and player_id so rows with equal keys always
+// come back in the same order. Reversed when reading backwards from a cursor.
+fn sort_sql(params: &QueryParams, backward: bool) -> String {
+    let direction = |asc: bool| if asc != backward { "ASC" } else { "DESC" };
+
+    let mut keys: Vec<String> = params
+        .sort
+        .orders()
+        .iter()
+        .map(|order| {
+            format!(
+                "{} {} NULLS {}",
+                order.sql,
+                direction(order.asc),
+                if order.nulls_first != backward { "FIRST" } else { "LAST" }
+            )
+        })
+        .collect();
+    let ids = direction(params.sort.ascending());
+    keys.push(format!("game_id {}, player_id {}", ids, ids));
+
+    format!(" ORDER BY {}", keys.join(", "))
+}
 
-    let count_query = query.replace(
-        "SELECT player_id, game_id, team_id, season, player, team, match_up, game_date, w_l, min, pts, fgm, fga, fg_percent, three_pm, three_pa, three_p_percent, ftm, fta, ft_percent, oreb, dreb, reb, ast, stl, blk, tov, pf, plus_minus, fp FROM player_box_scores",
-        "SELECT COUNT(*) FROM player_box_scores"
+// Cursor condition, ORDER BY, LIMIT and OFFSET for params. One row more than the limit
+// is read to tell whether another page follows.
+pub fn order_sql(params: &QueryParams) -> String {
+    let limit = params.limit.unwrap_or(50);
+    let offset = params.offset.unwrap_or(0);
+
+    match &params.page_cursor {
+        Some(cursor) => format!(
+            "{} LIMIT {}",
+            sort_sql(params, cursor.backward),
+            limit + 1
+        ),
+        None => format!("{} LIMIT {} OFFSET {}", sort_sql(params, false), limit + 1, offset),
+    }
+}
+
+// The SELECT query_boxscores runs for params, with the sort keys as extra last columns
+pub fn boxscores_sql(params: &QueryParams) -> BoundSql {
+    let orders = params.sort.orders();
+    let seek = params
+        .page_cursor
+        .as_ref()
+        .map(|cursor| cursor.where_sql(&orders, params.sort.ascending()))
+        .unwrap_or_default();
+    let keys: Vec<String> = orders
+        .iter()
+        .enumerate()
+        .map(|(idx, order)| format!("({})::text AS sort_key_{}", order.sql, idx))
+        .collect();
+
+    let mut values = Vec::new();
+    let sql = format!(
+        "SELECT {}, {} FROM player_box_scores{}{}{}",
+        BOX_SCORE_COLUMNS,
+        keys.join(", "),
+        where_sql(params, &mut values),
+        seek,
+        order_sql(params)
+    );
+    BoundSql { sql, params: values }
+}
+
+// Exports return the whole filtered result set, paginated only when limit or offset is given
+pub fn export_sql(params: &QueryParams) -> BoundSql {
+    let mut values = Vec::new();
+    let mut query = format!(
+        "SELECT {} FROM player_box_scores{}{}",
+        BOX_SCORE_COLUMNS,
+        where_sql(params, &mut values),
+        sort_sql(params, false)
     );
+    if let Some(limit) = params.limit {
+        query.push_str(&format!(" LIMIT {}", limit));
+    }
+    if let Some(offset) = params.offset {
+        query.push_str(&format!(" OFFSET {}", offset));
+    }
+    BoundSql { sql: query, params: values }
+}
+
+// Number of box scores matching params, and per group when group_by is given
+pub async fn count_boxscores(
+    client: &Client,
+    params: &QueryParams,
+    group_by: Option<CountGroup>,
+) -> Result<CountResponse, String> {
+    let mut values = Vec::new();
+    let filters = where_sql(params, &mut values);
+    let query = BoundSql {
+        sql: match group_by {
+            Some(group) => format!(
+                "SELECT {columns}, COUNT(*) FROM player_box_scores{} GROUP BY {columns} ORDER BY COUNT(*) DESC, {columns}",
+                filters,
+                columns = match group.id_column() {
+                    Some(id) => format!("{}, {}", group.column(), id),
+                    None => group.column().to_string(),
+                }
+            ),
+            None => format!("SELECT COUNT(*) FROM player_box_scores{}", filters),
+        },
+        params: values,
+    };
+
+    let rows = client
+        .query(&query.sql, &query.refs())
+        .await
+        .map_err(|e| format!("Count query error: {}", e))?;
+
+    if group_by.is_none() {
+        let count: i64 = rows.first().map(|row| row.get(0)).unwrap_or(0);
+        return Ok(CountResponse { count, groups: None });
+    }
+
+    let with_id = group_by.is_some_and(|group| group.id_column().is_some());
+    let groups: Vec<GroupCount> = rows
+        .iter()
+        .map(|row| {
+            let value = row.get::<_, Option<String>>(0).unwrap_or_default();
+            if with_id {
+                GroupCount { value, player_id: row.get(1), count: row.get(2) }
+            } else {
+                GroupCount { value, player_id: None, count: row.get(1) }
+            }
+        })
+        .collect();
+    Ok(CountResponse {
+        count: groups.iter().map(|group| group.count).sum(),
+        groups: Some(groups),
+    })
+}
+
+pub async fn query_boxscores(
+    client: &Client,
+    params: QueryParams,
+) -> Result<PaginatedResponse, String> {
+    let mut count_values = Vec::new();
+    let count_query = BoundSql {
+        sql: format!("SELECT COUNT(*) FROM player_box_scores{}", where_sql(&params, &mut count_values)),
+        params: count_values,
+    };
 
     let count_row = client
-        .query_one(&count_query, &[])
+        .query_one(&count_query.sql, &count_query.refs())
         .await
         .map_err(|e| format!("Count query error: {}", e))?;
 
@@ -104,20 +311,41 @@ pub async fn query_boxscores(
     let limit = params.limit.unwrap_or(50);
     let offset = params.offset.unwrap_or(0);
 
-    let sort_sql = params.sort.sort_by
-        .as_ref()
-        .map(|expr| expr.as_sql())
-        .unwrap_or_else(|| "game_date".to_string());
-
-    let order = if params.sort.asc.unwrap_or(false) { "ASC" } else { "DESC" };
-
-    query.push_str(&format!(" ORDER BY {} {} LIMIT {} OFFSET {}", sort_sql, order, limit, offset));
+    let query = boxscores_sql(&params);
 
-    let rows = client
-        .query(&query, &[])
+    let mut rows = client
+        .query(&query.sql, &query.refs())
         .await
         .map_err(|e| format!("Query error: {}", e))?;
 
+    // The extra row only says whether the listing continues past this page
+    let more = rows.len() as i64 > limit;
+    rows.truncate(limit.max(0) as usize);
+    let backward = params.page_cursor.as_ref().is_some_and(|cursor| cursor.backward);
+    if backward {
+        rows.reverse();
+    }
+
+    let sort = params.sort.signature();
+    let key_count = params.sort.orders().len();
+    let cursor_at = |row: &tokio_postgres::Row, backward: bool| {
+        Cursor {
+            sort: sort.clone(),
+            keys: (30..30 + key_count).map(|idx| row.get(idx)).collect(),
+            game_id: row.get(1),
+            player_id: row.get(0),
+            backward,
+        }
+        .encode()
+    };
+    let (has_next, has_prev) = if backward {
+        (true, more)
+    } else {
+        (more, params.page_cursor.is_some() || offset > 0)
+    };
+    let next_cursor = rows.last().filter(|_| has_next).map(|row| cursor_at(row, false));
+    let prev_cursor = rows.first().filter(|_| has_prev).map(|row| cursor_at(row, true));
+
     let box_scores: Vec<BoxScore> = rows
         .iter()
         .map(|row| BoxScore {
@@ -160,6 +388,9 @@ pub async fn query_boxscores(
         limit,
         offset,
         explicit_limit,
+        next_cursor,
+        prev_cursor,
         query_params: params,
+        meta: None,
     })
 }
@@ -0,0 +1,630 @@
+use serde::Serialize;
+use serde_json::Value;
+use sqlparser::ast::{
+    BinaryOperator, Expr, FunctionArg, FunctionArgExpr, FunctionArguments, GroupByExpr, Query, SelectItem, SetExpr,
+    Statement, TableFactor, UnaryOperator, Value as SqlValue,
+};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
+use std::collections::HashMap;
+use tokio_postgres::types::{FromSql, Type};
+use tokio_postgres::Client;
+use utoipa::ToSchema;
+
+use super::boxscores::filters::{ConditionField, Filter, FilterGroup};
+use super::boxscores::models::{Operation, QueryParams, SortExpression, SortField};
+use super::db::BoundSql;
+
+// Plain-English description of what a query returns. Built from the QueryParams or the
+// parsed SQL, never by the LLM, so it always matches what was actually run.
+#[derive(Serialize, Clone, ToSchema)]
+pub struct Explanation {
+    pub summary: String,
+    pub filters: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grouping: Option<String>,
+    pub sort: String,
+    // Composite score the results are ranked by, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub formula: Option<String>,
+    pub limit: String,
+    // Postgres EXPLAIN estimates, only returned to admins
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plan: Option<QueryPlan>,
+}
+
+#[derive(Serialize, Clone, ToSchema)]
+pub struct QueryPlan {
+    pub node_type: String,
+    pub startup_cost: f64,
+    pub total_cost: f64,
+    pub plan_rows: f64,
+    #[schema(value_type = Object)]
+    pub plan: Value,
+}
+
+pub fn explain_params(params: &QueryParams) -> Explanation {
+    let minimums = [
+        (SortField::Pts, params.pts.map(f64::from)),
+        (SortField::Reb, params.reb.map(f64::from)),
+        (SortField::Ast, params.ast.map(f64::from)),
+        (SortField::Stl, params.stl.map(f64::from)),
+        (SortField::Blk, params.blk.map(f64::from)),
+        (SortField::Fgm, params.fgm.map(f64::from)),
+        (SortField::Fga, params.fga.map(f64::from)),
+        (SortField::FgPercent, params.fg_percent),
+        (SortField::ThreePm, params.three_pm.map(f64::from)),
+        (SortField::ThreePa, params.three_pa.map(f64::from)),
+        (SortField::ThreePPercent, params.three_p_percent),
+        (SortField::Ftm, params.ftm.map(f64::from)),
+        (SortField::Fta, params.fta.map(f64::from)),
+        (SortField::FtPercent, params.ft_percent),
+        (SortField::Oreb, params.oreb.map(f64::from)),
+        (SortField::Dreb, params.dreb.map(f64::from)),
+        (SortField::Tov, params.tov.map(f64::from)),
+        (SortField::Pf, params.pf.map(f64::from)),
+        (SortField::PlusMinus, params.plus_minus.map(f64::from)),
+        (SortField::Fp, params.fp),
+        (SortField::Min, params.min.map(f64::from)),
+    ];
+
+    let mut stats: Vec<String> = minimums
+        .iter()
+        .filter_map(|(field, value)| value.map(|v| at_least(field, v)))
+        .collect();
+    for filter in params.filters.iter().flatten() {
+        let expr = match &filter.expr {
+            SortExpression::Op(_) | SortExpression::Sum { .. } => format!("({})", formula_text(&filter.expr)),
+            other => formula_text(other),
+        };
+        match (filter.min, filter.max) {
+            (Some(min), Some(max)) => stats.push(format!("{} between {} and {}", expr, min, max)),
+            (Some(min), None) => stats.push(format!("{} of at least {}", expr, min)),
+            (None, Some(max)) => stats.push(format!("{} of at most {}", expr, max)),
+            (None, None) => {}
+        }
+    }
+
+    let mut others: Vec<String> = Vec::new();
+    match (&params.season, &params.season_from, &params.season_to) {
+        (Some(season), _, _) => others.push(format!("in the {} season", season)),
+        (None, Some(from), Some(to)) => others.push(format!("from the {} season through {}", from, to)),
+        (None, Some(from), None) => others.push(format!("from the {} season on", from)),
+        (None, None, Some(to)) => others.push(format!("up to the {} season", to)),
+        (None, None, None) => {}
+    }
+    if let Some(season_type) = &params.season_type {
+        others.push(format!("in {} games only", season_type.label()));
+    }
+    match (&params.player, &params.resolved_player_ids) {
+        (Some(player), Some(_)) => others.push(format!("by {}", player.values().join(" or "))),
+        (Some(player), None) => others.push(format!(
+            "by players whose name contains {}",
+            player.values().iter().map(|name| format!("'{}'", name)).collect::<Vec<_>>().join(" or ")
+        )),
+        _ => {}
+    }
+    if let Some(team) = &params.team {
+        others.push(format!("playing for {}", team.values().join(" or ")));
+    }
+    if let Some(opponent) = &params.opponent {
+        others.push(format!("against {}", opponent));
+    }
+    if let Some(player_id) = &params.player_id {
+        match player_id.values().as_slice() {
+            [id] => others.push(format!("for player id {}", id)),
+            ids => others.push(format!("for player ids {}", ids.join(", "))),
+        }
+    }
+    if let Some(game_id) = &params.game_id {
+        others.push(format!("in game {}", game_id));
+    }
+    if let Some(filter) = &params.filter {
+        others.push(format!("where {}", filter_text(filter)));
+    }
+
+    let mut sorts = Vec::new();
+    let mut formulas = Vec::new();
+    for (key, order) in params.sort.keys().iter().zip(params.sort.orders()) {
+        let mut sort = match &key.by {
+            SortExpression::Field(field) => format!("{}, {}", field.label(), direction(field, order.asc)),
+            SortExpression::Metric(metric) => {
+                format!("{}, {}", metric.label(), if order.asc { "lowest first" } else { "highest first" })
+            }
+            SortExpression::Constant(c) => format!("the constant {}", c),
+            SortExpression::Sum { .. } | SortExpression::Op(_) => {
+                formulas.push(formula_text(&key.by));
+                format!("composite score, {}", if order.asc { "lowest first" } else { "highest first" })
+            }
+        };
+        if order.nulls_first {
+            sort.push_str(", missing values first");
+        }
+        sorts.push(sort);
+    }
+    let sort = sorts.join(", then ");
+    let formula = (!formulas.is_empty()).then(|| formulas.join("; "));
+
+    let limit = match params.page_cursor {
+        Some(ref cursor) => format!(
+            "Up to {} rows {} the cursor position",
+            params.limit.unwrap_or(50),
+            if cursor.backward { "before" } else { "after" }
+        ),
+        None => limit_text(params.limit.unwrap_or(50), params.offset.unwrap_or(0)),
+    };
+
+    let mut description = String::from("Box scores");
+    if !stats.is_empty() {
+        description.push_str(&format!(" with {}", join_and(&stats)));
+    }
+    for phrase in &others {
+        description.push_str(&format!(", {}", phrase));
+    }
+    let summary = format!(
+        "{}. Sorted by {}{}. {}.",
+        description,
+        sort,
+        formula.as_ref().map(|f| format!(" ({})", f)).unwrap_or_default(),
+        limit
+    );
+
+    Explanation {
+        summary,
+        filters: stats.into_iter().chain(others).collect(),
+        grouping: None,
+        sort,
+        formula,
+        limit,
+        plan: None,
+    }
+}
+
+// Readable form of an expression, e.g. "points ÷ (field goals attempted + 0.44 × free throws attempted)"
+fn filter_text(filter: &Filter) -> String {
+    let group = |items: &[Filter], word: &str| {
+        items
+            .iter()
+            .map(|item| match item {
+                Filter::Group(FilterGroup::And(_) | FilterGroup::Or(_)) => format!("({})", filter_text(item)),
+                other => filter_text(other),
+            })
+            .collect::<Vec<_>>()
+            .join(word)
+    };
+    match filter {
+        Filter::Group(FilterGroup::And(items)) => group(items, " and "),
+        Filter::Group(FilterGroup::Or(items)) => group(items, " or "),
+        Filter::Group(FilterGroup::Not(inner)) => format!("not ({})", filter_text(inner)),
+        Filter::Condition(condition) => {
+            let field = match &condition.field {
+                ConditionField::Text(field) => field.label().to_string(),
+                ConditionField::Expression(expr @ (SortExpression::Op(_) | SortExpression::Sum { .. })) => {
+                    format!("({})", formula_text(expr))
+                }
+                ConditionField::Expression(expr) => formula_text(expr),
+            };
+            let value = match &condition.value {
+                Value::Array(values) => values.iter().map(value_text).collect::<Vec<_>>().join(", "),
+                value => value_text(value),
+            };
+            format!("{} {} {}", field, condition.op.label(), value)
+        }
+    }
+}
+
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn formula_text(expr: &SortExpression) -> String {
+    match expr {
+        SortExpression::Field(field) => field.label().to_string(),
+        SortExpression::Metric(metric) => metric.label().to_string(),
+        SortExpression::Constant(c) => c.to_string(),
+        SortExpression::Sum { terms } => terms
+            .iter()
+            .enumerate()
+            .map(|(i, term)| {
+                let sign = match (i, term.weight < 0.0) {
+                    (0, false) => "",
+                    (0, true) => "-",
+                    (_, false) => " + ",
+                    (_, true) => " - ",
+                };
+                format!("{}{} × {}", sign, term.weight.abs(), term.field.label())
+            })
+            .collect::<String>(),
+        SortExpression::Op(op) => {
+            let symbol = match op {
+                Operation::Add(_) => " + ",
+                Operation::Sub(_) => " - ",
+                Operation::Mul(_) => " × ",
+                Operation::Div(_) => " ÷ ",
+            };
+            op.operands()
+                .iter()
+                .map(|operand| match operand {
+                    SortExpression::Op(_) | SortExpression::Sum { .. } => format!("({})", formula_text(operand)),
+                    other => formula_text(other),
+                })
+                .collect::<Vec<_>>()
+                .join(symbol)
+        }
+    }
+}
+
+fn at_least(field: &SortField, value: f64) -> String {
+    match field {
+        SortField::FgPercent | SortField::ThreePPercent | SortField::FtPercent => {
+            format!("{} of at least {}%", field.label(), value)
+        }
+        SortField::PlusMinus => format!("{} of at least {:+}", field.label(), value),
+        _ => format!("at least {} {}", value, field.label()),
+    }
+}
+
+fn direction(field: &SortField, asc: bool) -> &'static str {
+    match (field, asc) {
+        (SortField::GameDate, true) => "oldest first",
+        (SortField::GameDate, false) => "most recent first",
+        (_, true) => "lowest first",
+        (_, false) => "highest first",
+    }
+}
+
+fn limit_text(limit: i64, offset: i64) -> String {
+    if offset > 0 {
+        format!("Up to {} rows, skipping the first {}", limit, offset)
+    } else {
+        format!("Up to {} rows", limit)
+    }
+}
+
+fn join_and(items: &[String]) -> String {
+    match items {
+        [] => String::new(),
+        [only] => only.clone(),
+        [rest @ .., last] => format!("{} and {}", rest.join(", "), last),
+    }
+}
+
+pub fn explain_sql(sql: &str) -> Result<Explanation, String> {
+    let statements = Parser::parse_sql(&PostgreSqlDialect {}, sql)
+        .map_err(|e| format!("SQL could not be parsed: {}", e))?;
+    let [Statement::Query(query)] = statements.as_slice() else {
+        return Err("Only single SELECT queries can be explained".to_string());
+    };
+    Ok(explain_query(query))
+}
+
+fn explain_query(query: &Query) -> Explanation {
+    let helpers: Vec<String> = query
+        .with
+        .iter()
+        .flat_map(|with| with.cte_tables.iter().map(|cte| cte.alias.name.value.clone()))
+        .collect();
+
+    let mut sources: Vec<String> = Vec::new();
+    let mut outputs: Vec<String> = Vec::new();
+    let mut filters: Vec<String> = Vec::new();
+    let mut group_filters: Vec<String> = Vec::new();
+    let mut grouping = None;
+    let mut aliases: HashMap<String, &Expr> = HashMap::new();
+    let mut distinct = false;
+
+    match query.body.as_ref() {
+        SetExpr::Select(select) => {
+            distinct = select.distinct.is_some();
+            for table in &select.from {
+                for factor in std::iter::once(&table.relation).chain(table.joins.iter().map(|j| &j.relation)) {
+                    match factor {
+                        TableFactor::Table { name, .. } => sources.push(name.to_string()),
+                        TableFactor::Derived { .. } => sources.push("a subquery".to_string()),
+                        other => sources.push(other.to_string()),
+                    }
+                }
+            }
+            for item in &select.projection {
+                match item {
+                    SelectItem::ExprWithAlias { expr, alias } => {
+                        aliases.insert(alias.value.to_lowercase(), expr);
+                        if is_computed(expr) {
+                            outputs.push(format!("{} as {}", describe(unnest(expr)), alias.value));
+                        }
+                    }
+                    SelectItem::UnnamedExpr(expr) if is_computed(expr) => outputs.push(describe(unnest(expr))),
+                    _ => {}
+                }
+            }
+            if let Some(selection) = &select.selection {
+                filters.extend(conjuncts(selection).into_iter().map(describe_condition));
+            }
+            if let GroupByExpr::Expressions(exprs, _) = &select.group_by
+                && !exprs.is_empty()
+            {
+                grouping = Some(exprs.iter().map(describe).collect::<Vec<_>>().join(", "));
+            }
+            if let Some(having) = &select.having {
+                group_filters.extend(conjuncts(having).into_iter().map(describe_condition));
+            }
+        }
+        SetExpr::SetOperation { op, .. } => sources.push(format!("the {} of several queries", op).to_lowercase()),
+        other => sources.push(other.to_string()),
+    }
+
+    let mut formula = None;
+    let sort = match &query.order_by {
+        Some(order_by) if !order_by.exprs.is_empty() => order_by
+            .exprs
+            .iter()
+            .map(|item| {
+                let target = match &item.expr {
+                    Expr::Identifier(ident) => aliases.get(&ident.value.to_lowercase()).copied(),
+                    _ => None,
+                };
+                // Sorting by an alias of a computed column explains the computation
+                let shown = match target {
+                    Some(expr) if is_formula(expr) && formula.is_none() => {
+                        formula = Some(describe(unnest(expr)));
+                        describe(&item.expr)
+                    }
+                    Some(expr) if is_computed(expr) => describe(unnest(expr)),
+                    _ if is_formula(&item.expr) && formula.is_none() => {
+                        formula = Some(describe(unnest(&item.expr)));
+                        "composite score".to_string()
+                    }
+                    _ => describe(unnest(&item.expr)),
+                };
+                // Postgres sorts ascending unless told otherwise
+                let asc = item.asc.unwrap_or(true);
+                format!("{}, {}", shown, if asc { "lowest first" } else { "highest first" })
+            })
+            .collect::<Vec<_>>()
+            .join(", then by "),
+        _ => "no particular order".to_string(),
+    };
+
+    let limit = match &query.limit {
+        Some(limit) => {
+            let offset = query
+                .offset
+                .as_ref()
+                .and_then(|o| o.value.to_string().parse().ok())
+                .unwrap_or(0);
+            match limit.to_string().parse() {
+                Ok(n) => limit_text(n, offset),
+                Err(_) => format!("Up to {} rows", limit),
+            }
+        }
+        None => "No row limit".to_string(),
+    };
+
+    let mut parts = vec![format!(
+        "Reads {}{}",
+        if sources.is_empty() { "no table".to_string() } else { join_and(&sources) },
+        if helpers.is_empty() {
+            String::new()
+        } else {
+            format!(" ({} defined in a WITH clause)", join_and(&helpers))
+        }
+    )];
+    if !filters.is_empty() {
+        parts.push(format!("keeping rows where {}", join_and(&filters)));
+    }
+    if let Some(grouping) = &grouping {
+        parts.push(format!("grouped by {}", grouping));
+    }
+    if !group_filters.is_empty() {
+        parts.push(format!("keeping groups where {}", join_and(&group_filters)));
+    }
+    if !outputs.is_empty() {
+        parts.push(format!("computing {}", join_and(&outputs)));
+    }
+    if distinct {
+        parts.push("without duplicate rows".to_string());
+    }
+    let summary = format!(
+        "{}. Sorted by {}{}. {}.",
+        parts.join(", "),
+        sort,
+        formula.as_ref().map(|f| format!(" ({})", f)).unwrap_or_default(),
+        limit
+    );
+
+    filters.extend(group_filters.into_iter().map(|f| format!("groups where {}", f)));
+
+    Explanation {
+        summary,
+        filters,
+        grouping,
+        sort,
+        formula,
+        limit,
+        plan: None,
+    }
+}
+
+// Anything other than a plain column or literal
+fn is_computed(expr: &Expr) -> bool {
+    !matches!(
+        expr,
+        Expr::Identifier(_) | Expr::CompoundIdentifier(_) | Expr::Value(_)
+    )
+}
+
+// Arithmetic over columns, e.g. a composite score
+fn is_formula(expr: &Expr) -> bool {
+    matches!(unnest(expr), Expr::BinaryOp { .. })
+}
+
+fn unnest(expr: &Expr) -> &Expr {
+    match expr {
+        Expr::Nested(inner) => unnest(inner),
+        other => other,
+    }
+}
+
+fn conjuncts(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::BinaryOp { left, op: BinaryOperator::And, right } => {
+            let mut parts = conjuncts(left);
+            parts.extend(conjuncts(right));
+            parts
+        }
+        Expr::Nested(inner) if matches!(inner.as_ref(), Expr::BinaryOp { op: BinaryOperator::And, .. }) => {
+            conjuncts(inner)
+        }
+        other => vec![other],
+    }
+}
+
+fn describe_condition(expr: &Expr) -> String {
+    match expr {
+        Expr::BinaryOp { left, op, right } => {
+            let words = match op {
+                BinaryOperator::Gt => "is above",
+                BinaryOperator::GtEq => "is at least",
+                BinaryOperator::Lt => "is below",
+                BinaryOperator::LtEq => "is at most",
+                BinaryOperator::Eq => "is",
+                BinaryOperator::NotEq => "is not",
+                BinaryOperator::Or => {
+                    return format!("{} or {}", describe_condition(left), describe_condition(right));
+                }
+                BinaryOperator::And => {
+                    return format!("{} and {}", describe_condition(left), describe_condition(right));
+                }
+                _ => return describe(expr),
+            };
+            format!("{} {} {}", describe(left), words, describe(right))
+        }
+        Expr::Between { expr, negated, low, high } => format!(
+            "{} is {}between {} and {}",
+            describe(expr),
+            if *negated { "not " } else { "" },
+            describe(low),
+            describe(high)
+        ),
+        Expr::InList { expr, list, negated } => format!(
+            "{} is {}one of {}",
+            describe(expr),
+            if *negated { "not " } else { "" },
+            list.iter().map(describe).collect::<Vec<_>>().join(", ")
+        ),
+        Expr::Like { expr, negated, pattern, .. } | Expr::ILike { expr, negated, pattern, .. } => format!(
+            "{} {} {}",
+            describe(expr),
+            if *negated { "does not match" } else { "matches" },
+            describe(pattern)
+        ),
+        Expr::IsNull(inner) => format!("{} is missing", describe(inner)),
+        Expr::IsNotNull(inner) => format!("{} is recorded", describe(inner)),
+        Expr::UnaryOp { op: UnaryOperator::Not, expr } => format!("not ({})", describe_condition(expr)),
+        Expr::Nested(inner) => format!("({})", describe_condition(inner)),
+        other => describe(other),
+    }
+}
+
+fn describe(expr: &Expr) -> String {
+    match expr {
+        Expr::Identifier(ident) => column_label(&ident.value),
+        Expr::CompoundIdentifier(parts) => parts.last().map(|p| column_label(&p.value)).unwrap_or_default(),
+        Expr::Value(SqlValue::SingleQuotedString(s)) => format!("'{}'", s),
+        Expr::Value(value) => value.to_string(),
+        Expr::Nested(inner) => format!("({})", describe(inner)),
+        Expr::Cast { expr, .. } => describe(expr),
+        Expr::UnaryOp { op: UnaryOperator::Minus, expr } => format!("-{}", describe(expr)),
+        Expr::BinaryOp { left, op, right } => {
+            let symbol = match op {
+                BinaryOperator::Plus => "+".to_string(),
+                BinaryOperator::Minus => "-".to_string(),
+                BinaryOperator::Multiply => "×".to_string(),
+                BinaryOperator::Divide => "÷".to_string(),
+                _ => return describe_condition(expr),
+            };
+            format!("{} {} {}", describe(left), symbol, describe(right))
+        }
+        Expr::Function(function) => {
+            let name = function.name.to_string().to_lowercase();
+            let args: Vec<String> = match &function.args {
+                FunctionArguments::List(list) => list
+                    .args
+                    .iter()
+                    .map(|arg| match arg {
+                        FunctionArg::Unnamed(FunctionArgExpr::Expr(e)) => describe(e),
+                        FunctionArg::Unnamed(FunctionArgExpr::Wildcard) => "*".to_string(),
+                        other => other.to_string(),
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            };
+            let first = args.first().cloned().unwrap_or_default();
+            match name.as_str() {
+                "count" if first == "*" || first.is_empty() => "number of games".to_string(),
+                "count" => format!("number of {}", first),
+                "avg" => format!("average {}", first),
+                "sum" => format!("total {}", first),
+                "max" => format!("highest {}", first),
+                "min" => format!("lowest {}", first),
+                "round" | "coalesce" | "nullif" => first,
+                _ => format!("{}({})", name, args.join(", ")),
+            }
+        }
+        other => other.to_string(),
+    }
+}
+
+fn column_label(column: &str) -> String {
+    let lowered = column.to_lowercase();
+    if let Ok(field) = serde_json::from_value::<SortField>(Value::String(lowered.clone())) {
+        return field.label().to_string();
+    }
+    match lowered.as_str() {
+        "w_l" => "result".to_string(),
+        "match_up" => "matchup".to_string(),
+        _ => lowered.replace('_', " "),
+    }
+}
+
+// EXPLAIN (FORMAT JSON) returns a json column, which tokio_postgres only reads with its
+// serde feature, so the raw bytes are taken as text instead
+struct RawJson(String);
+
+impl<'a> FromSql<'a> for RawJson {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+        Ok(RawJson(std::str::from_utf8(raw)?.to_string()))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::JSON || *ty == Type::TEXT
+    }
+}
+
+// EXPLAIN estimates for sql, run with its bound parameters
+pub async fn query_plan(client: &Client, sql: &BoundSql) -> Result<QueryPlan, String> {
+    let row = client
+        .query_one(&format!("EXPLAIN (FORMAT JSON) {}", sql.sql), &sql.refs())
+        .await
+        .map_err(|e| format!("EXPLAIN failed: {}", e))?;
+    let RawJson(text) = row.try_get(0).map_err(|e| format!("Invalid EXPLAIN output: {}", e))?;
+
+    let parsed: Value = serde_json::from_str(&text).map_err(|e| format!("Invalid EXPLAIN output: {}", e))?;
+    let plan = parsed
+        .get(0)
+        .and_then(|p| p.get("Plan"))
+        .cloned()
+        .ok_or("EXPLAIN output has no plan")?;
+
+    let number = |key: &str| plan.get(key).and_then(Value::as_f64).unwrap_or(0.0);
+    Ok(QueryPlan {
+        node_type: plan.get("Node Type").and_then(Value::as_str).unwrap_or_default().to_string(),
+        startup_cost: number("Startup Cost"),
+        total_cost: number("Total Cost"),
+        plan_rows: number("Plan Rows"),
+        plan,
+    })
+}
@@ -0,0 +1,470 @@
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures_util::stream;
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, Semaphore};
+use tokio::time::timeout;
+use tokio_postgres::{Client, Column, NoTls, Portal, Row, Statement, Transaction};
+use utoipa::IntoParams;
+
+use super::columnar::{arrow_schema, record_batch, BatchWriter, BATCH_ROWS};
+use super::sql::column_value;
+use super::db::BoundSql;
+
+// Rows fetched from the cursor at a time for the text formats
+const FETCH_ROWS: i32 = 1000;
+
+// Encoded chunks buffered between the cursor and the client. Postgres is only read as
+// fast as the client downloads.
+const CHANNEL_CHUNKS: usize = 4;
+
+const DEFAULT_MAX_ROWS: usize = 1_000_000;
+
+// Limit on each fetch from a cursor over generated SQL
+const READONLY_STATEMENT_TIMEOUT_MS: u64 = 10_000;
+
+// Limit on each fetch from a cursor over the box score filters, generous since exports can
+// be large but bounded so one export can't hold the primary indefinitely
+const PRIMARY_STATEMENT_TIMEOUT_MS: u64 = 30_000;
+
+// Streams open at once, each on its own connection (EXPORT_MAX_STREAMS, default 8)
+const DEFAULT_MAX_STREAMS: usize = 8;
+
+// How long a request waits for a free stream before it's turned away
+const STREAM_WAIT: Duration = Duration::from_secs(5);
+
+static STREAM_SLOTS: LazyLock<Arc<Semaphore>> = LazyLock::new(|| {
+    let streams = std::env::var("EXPORT_MAX_STREAMS")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(DEFAULT_MAX_STREAMS);
+    Arc::new(Semaphore::new(streams))
+});
+
+#[derive(Deserialize, IntoParams)]
+pub struct FormatParams {
+    /// Response format: json (default), csv, ndjson, arrow (IPC stream) or parquet. Overrides the Accept header.
+    pub format: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    Ndjson,
+    Arrow,
+    Parquet,
+}
+
+impl ExportFormat {
+    // ?format= wins over the Accept header; anything unrecognised in Accept falls back to JSON
+    pub fn negotiate(headers: &HeaderMap, format: Option<&str>) -> Result<Self, (StatusCode, String)> {
+        if let Some(format) = format {
+            return match format.trim().to_lowercase().as_str() {
+                "json" => Ok(ExportFormat::Json),
+                "csv" => Ok(ExportFormat::Csv),
+                "ndjson" | "jsonl" => Ok(ExportFormat::Ndjson),
+                "arrow" | "arrows" => Ok(ExportFormat::Arrow),
+                "parquet" => Ok(ExportFormat::Parquet),
+                other => Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("Unsupported format '{}', expected json, csv, ndjson, arrow or parquet", other),
+                )),
+            };
+        }
+
+        let accept = headers
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_lowercase();
+        if accept.contains("text/csv") {
+            Ok(ExportFormat::Csv)
+        } else if accept.contains("application/x-ndjson") || accept.contains("application/ndjson") {
+            Ok(ExportFormat::Ndjson)
+        } else if accept.contains("application/vnd.apache.arrow.stream") {
+            Ok(ExportFormat::Arrow)
+        } else if accept.contains("application/vnd.apache.parquet") || accept.contains("application/x-parquet") {
+            Ok(ExportFormat::Parquet)
+        } else {
+            Ok(ExportFormat::Json)
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+            ExportFormat::Arrow => "application/vnd.apache.arrow.stream",
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::Arrow => "arrows",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+}
+
+// Database a stream reads from. Each stream opens its own connection so a slow download
+// never holds up queries on the shared clients; STREAM_SLOTS bounds how many are open.
+#[derive(Clone, Copy)]
+pub enum ExportSource {
+    Primary,
+    ReadOnly,
+}
+
+impl ExportSource {
+    fn url(&self) -> Result<String, String> {
+        let var = match self {
+            ExportSource::Primary => "DATABASE_URL",
+            ExportSource::ReadOnly => "DATABASE_URL_READONLY",
+        };
+        std::env::var(var).map_err(|_| format!("{} not set", var))
+    }
+
+    // Generated SQL keeps the same per-query limit it has on the shared client
+    fn statement_timeout_ms(&self) -> u64 {
+        match self {
+            ExportSource::Primary => PRIMARY_STATEMENT_TIMEOUT_MS,
+            ExportSource::ReadOnly => READONLY_STATEMENT_TIMEOUT_MS,
+        }
+    }
+}
+
+// Fields written after "data" in a streamed JSON response, given the number of rows sent
+pub type JsonTail = Box<dyn FnOnce(usize) -> Value + Send>;
+
+// EXPORT_MAX_ROWS caps every streamed response (default: 1000000). JSON stops at the cap
+// with "truncated": true; the file formats have no place for that and fail instead.
+pub fn max_rows() -> usize {
+    std::env::var("EXPORT_MAX_ROWS")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(DEFAULT_MAX_ROWS)
+}
+
+// Streams every row of sql as a downloadable file in a text or columnar format
+pub async fn export_rows(
+    source: ExportSource,
+    sql: BoundSql,
+    format: ExportFormat,
+    name: &str,
+) -> Result<Response, (StatusCode, String)> {
+    let mut response = stream_query(source, sql, format, None).await?;
+    let disposition = format!("attachment; filename=\"{}.{}\"", name, format.extension());
+    if let Ok(value) = HeaderValue::from_str(&disposition) {
+        response.headers_mut().insert(header::CONTENT_DISPOSITION, value);
+    }
+    Ok(response)
+}
+
+// Streams the rows of sql as {"data": [...], <tail>}, with the tail fields computed once
+// the last row has been sent
+pub async fn stream_json(source: ExportSource, sql: BoundSql, tail: JsonTail) -> Result<Response, (StatusCode, String)> {
+    stream_query(source, sql, ExportFormat::Json, Some(tail)).await
+}
+
+// Runs sql through a cursor in a background task and returns a chunked response fed by it.
+// Errors before the first row (bad SQL, connection failures) are returned here; later
+// errors abort the body. The task holds a stream slot until it finishes.
+async fn stream_query(
+    source: ExportSource,
+    sql: BoundSql,
+    format: ExportFormat,
+    tail: Option<JsonTail>,
+) -> Result<Response, (StatusCode, String)> {
+    let max_rows = max_rows();
+
+    let busy = || (
+        StatusCode::SERVICE_UNAVAILABLE,
+        "Too many exports in progress, try again shortly".to_string(),
+    );
+    let slot = timeout(STREAM_WAIT, STREAM_SLOTS.clone().acquire_owned())
+        .await
+        .map_err(|_| busy())?
+        .map_err(|_| busy())?;
+
+    let (ready_tx, ready_rx) = oneshot::channel();
+    let (chunk_tx, chunk_rx) = mpsc::channel(CHANNEL_CHUNKS);
+    tokio::spawn(async move {
+        run_cursor(source, sql, format, tail, max_rows, ready_tx, chunk_tx).await;
+        drop(slot);
+    });
+
+    ready_rx
+        .await
+        .map_err(|_| "Export stopped before it started".to_string())
+        .and_then(|ready| ready)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    let chunks = stream::unfold(chunk_rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    });
+
+    let mut response = Body::from_stream(chunks).into_response();
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(format.content_type()));
+    headers.insert("x-row-limit", HeaderValue::from(max_rows));
+    Ok(response)
+}
+
+async fn open_cursor<'a>(
+    client: &'a mut Client,
+    source: ExportSource,
+    sql: &BoundSql,
+) -> Result<(Transaction<'a>, Statement, Portal), String> {
+    let transaction = client
+        .build_transaction()
+        .read_only(true)
+        .start()
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+    transaction
+        .batch_execute(&format!("SET LOCAL statement_timeout = {}", source.statement_timeout_ms()))
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+    let statement = transaction
+        .prepare(&sql.sql)
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+    let portal = transaction
+        .bind(&statement, &sql.refs())
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+    Ok((transaction, statement, portal))
+}
+
+async fn run_cursor(
+    source: ExportSource,
+    sql: BoundSql,
+    format: ExportFormat,
+    tail: Option<JsonTail>,
+    max_rows: usize,
+    ready: oneshot::Sender<Result<(), String>>,
+    chunks: mpsc::Sender<Result<Vec<u8>, String>>,
+) {
+    let url = match source.url() {
+        Ok(url) => url,
+        Err(e) => {
+            let _ = ready.send(Err(e));
+            return;
+        }
+    };
+    let mut client = match tokio_postgres::connect(&url, NoTls).await {
+        Ok((client, connection)) => {
+            tokio::spawn(async move {
+                if let Err(e) = connection.await {
+                    eprintln!("Export connection error: {}", e);
+                }
+            });
+            client
+        }
+        Err(e) => {
+            let _ = ready.send(Err(format!("Connection error: {}", e)));
+            return;
+        }
+    };
+
+    let opened = open_cursor(&mut client, source, &sql)
+        .await
+        .and_then(|(transaction, statement, portal)| {
+            let encoder = RowEncoder::new(format, statement.columns(), tail)?;
+            Ok((transaction, portal, encoder))
+        });
+    let (transaction, portal, mut encoder) = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            let _ = ready.send(Err(e));
+            return;
+        }
+    };
+    if ready.send(Ok(())).is_err() {
+        return;
+    }
+
+    let fetch = match format {
+        ExportFormat::Arrow | ExportFormat::Parquet => BATCH_ROWS as i32,
+        _ => FETCH_ROWS,
+    };
+    let header = encoder.header();
+    if !header.is_empty() && chunks.send(Ok(header)).await.is_err() {
+        return;
+    }
+
+    let mut sent = 0;
+    let mut truncated = false;
+    loop {
+        let mut rows = match transaction.query_portal(&portal, fetch).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                eprintln!("Export stream error: {}", e);
+                let _ = chunks.send(Err(format!("Database error: {}", e))).await;
+                return;
+            }
+        };
+        if rows.is_empty() {
+            break;
+        }
+
+        let room = max_rows - sent;
+        if rows.len() > room {
+            // Aborting the body makes the client see a failed download, not a short file
+            if format != ExportFormat::Json {
+                eprintln!("Export aborted, more than {} rows (EXPORT_MAX_ROWS)", max_rows);
+                let _ = chunks
+                    .send(Err(format!("Export has more than {} rows (EXPORT_MAX_ROWS)", max_rows)))
+                    .await;
+                return;
+            }
+            rows.truncate(room);
+            truncated = true;
+        }
+        if !rows.is_empty() {
+            sent += rows.len();
+            let bytes = encoder.encode(&rows);
+            if let Err(e) = &bytes {
+                eprintln!("Export stream error: {}", e);
+            }
+            // A closed channel means the client went away
+            if chunks.send(bytes).await.is_err() {
+                return;
+            }
+        }
+        if truncated {
+            eprintln!("Export truncated at {} rows (EXPORT_MAX_ROWS)", max_rows);
+            break;
+        }
+    }
+
+    let _ = chunks.send(encoder.finish(sent, truncated)).await;
+}
+
+// Serializes rows into the response format as they are fetched
+struct RowEncoder {
+    format: ExportFormat,
+    columns: Vec<String>,
+    batches: Option<(arrow_schema::SchemaRef, BatchWriter)>,
+    tail: Option<JsonTail>,
+    rows_written: usize,
+}
+
+impl RowEncoder {
+    fn new(format: ExportFormat, columns: &[Column], tail: Option<JsonTail>) -> Result<Self, String> {
+        let batches = match format {
+            ExportFormat::Arrow | ExportFormat::Parquet => {
+                let schema = arrow_schema(columns);
+                let writer = match format {
+                    ExportFormat::Arrow => BatchWriter::arrow(&schema)?,
+                    _ => BatchWriter::parquet(&schema)?,
+                };
+                Some((schema, writer))
+            }
+            _ => None,
+        };
+        Ok(RowEncoder {
+            format,
+            columns: columns.iter().map(|c| c.name().to_string()).collect(),
+            batches,
+            tail,
+            rows_written: 0,
+        })
+    }
+
+    // CSV gets a header line even when there are no rows
+    fn header(&self) -> Vec<u8> {
+        match self.format {
+            ExportFormat::Csv => csv_line(self.columns.iter().map(|c| csv_field(c))).into_bytes(),
+            ExportFormat::Json => b"{\"data\":[".to_vec(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn encode(&mut self, rows: &[Row]) -> Result<Vec<u8>, String> {
+        if let Some((schema, writer)) = &mut self.batches {
+            return writer.write(&record_batch(schema, rows)?);
+        }
+
+        let mut out = String::new();
+        for row in rows {
+            let values = (0..self.columns.len()).map(|idx| column_value(row, idx));
+            match self.format {
+                ExportFormat::Csv => out.push_str(&csv_line(values.map(|value| match value {
+                    Value::Null => String::new(),
+                    Value::String(s) => csv_field(&s),
+                    other => other.to_string(),
+                }))),
+                _ => {
+                    if self.format == ExportFormat::Json && self.rows_written > 0 {
+                        out.push(',');
+                    }
+                    out.push_str(&json_object(&self.columns, values));
+                    if self.format == ExportFormat::Ndjson {
+                        out.push('\n');
+                    }
+                }
+            }
+            self.rows_written += 1;
+        }
+        Ok(out.into_bytes())
+    }
+
+    // Ends the stream: the Arrow end marker, the Parquet footer or the JSON tail fields
+    fn finish(self, rows: usize, truncated: bool) -> Result<Vec<u8>, String> {
+        if let Some((_, writer)) = self.batches {
+            return writer.finish();
+        }
+        if self.format != ExportFormat::Json {
+            return Ok(Vec::new());
+        }
+
+        let mut fields = match self.tail.map(|tail| tail(rows)) {
+            Some(Value::Object(fields)) => fields,
+            _ => serde_json::Map::new(),
+        };
+        fields.insert("truncated".to_string(), Value::Bool(truncated));
+        let tail: Vec<String> = fields
+            .iter()
+            .map(|(key, value)| format!("{}:{}", Value::String(key.clone()), value))
+            .collect();
+        Ok(format!("],{}}}", tail.join(",")).into_bytes())
+    }
+}
+
+// Written by hand so the keys stay in column order
+fn json_object(columns: &[String], values: impl Iterator<Item = Value>) -> String {
+    let fields: Vec<String> = columns
+        .iter()
+        .zip(values)
+        .map(|(column, value)| format!("{}:{}", Value::String(column.clone()), value))
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+fn csv_line(fields: impl Iterator<Item = String>) -> String {
+    let mut line = fields.collect::<Vec<_>>().join(",");
+    line.push_str("\r\n");
+    line
+}
+
+// RFC 4180 quoting
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
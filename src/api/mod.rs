@@ -1,5 +1,17 @@
 pub mod boxscores;
+pub mod catalog;
+pub mod columnar;
+pub mod cursor;
 pub mod db;
+pub mod examples;
+pub mod explain;
+pub mod export;
+pub mod guard;
+pub mod players;
 pub mod query;
+pub mod rules;
+pub mod seasons;
 pub mod sql;
+pub mod teams;
 pub mod tools;
+pub mod usage;
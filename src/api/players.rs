@@ -0,0 +1,440 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use tokio_postgres::Client;
+
+use super::boxscores::filters::Filter;
+use super::boxscores::models::{QueryParams, ValueList};
+
+const MATCH_THRESHOLD: f64 = 0.6;
+const TIE_WINDOW: f64 = 0.02;
+
+// The player list is reloaded once it is this old, so new players become resolvable
+const CACHE_TTL: Duration = Duration::from_secs(600);
+
+// A name that matches nobody reloads a list older than this, in case the player is new
+const MISS_RELOAD_AFTER: Duration = Duration::from_secs(30);
+
+// Normalized nickname -> full player name
+const NICKNAMES: &[(&str, &str)] = &[
+    ("ad", "Anthony Davis"),
+    ("the brow", "Anthony Davis"),
+    ("lbj", "LeBron James"),
+    ("king james", "LeBron James"),
+    ("bron", "LeBron James"),
+    ("kd", "Kevin Durant"),
+    ("easy money sniper", "Kevin Durant"),
+    ("steph", "Stephen Curry"),
+    ("chef curry", "Stephen Curry"),
+    ("greek freak", "Giannis Antetokounmpo"),
+    ("giannis", "Giannis Antetokounmpo"),
+    ("joker", "Nikola Jokic"),
+    ("jokic", "Nikola Jokic"),
+    ("cp3", "Chris Paul"),
+    ("dame", "Damian Lillard"),
+    ("sga", "Shai Gilgeous-Alexander"),
+    ("shai", "Shai Gilgeous-Alexander"),
+    ("ant", "Anthony Edwards"),
+    ("ant man", "Anthony Edwards"),
+    ("luka", "Luka Doncic"),
+    ("pg13", "Paul George"),
+    ("kat", "Karl-Anthony Towns"),
+    ("the beard", "James Harden"),
+    ("spida", "Donovan Mitchell"),
+    ("wemby", "Victor Wembanyama"),
+    ("the process", "Joel Embiid"),
+    ("jimmy buckets", "Jimmy Butler"),
+    ("the claw", "Kawhi Leonard"),
+    ("russ", "Russell Westbrook"),
+    ("brodie", "Russell Westbrook"),
+    ("melo", "Carmelo Anthony"),
+    ("dwade", "Dwyane Wade"),
+    ("flash", "Dwyane Wade"),
+    ("kobe", "Kobe Bryant"),
+    ("black mamba", "Kobe Bryant"),
+    ("shaq", "Shaquille O'Neal"),
+    ("big fundamental", "Tim Duncan"),
+    ("dirk", "Dirk Nowitzki"),
+    ("jt", "Jayson Tatum"),
+    ("jb", "Jaylen Brown"),
+    ("zion", "Zion Williamson"),
+    ("ja", "Ja Morant"),
+    ("book", "Devin Booker"),
+    ("trae", "Trae Young"),
+];
+
+#[derive(Clone)]
+pub struct PlayerEntry {
+    pub player_id: String,
+    pub player: String,
+    normalized: String,
+}
+
+#[derive(Default)]
+pub struct PlayerResolver {
+    players: RwLock<Option<(Arc<Vec<PlayerEntry>>, Instant)>>,
+}
+
+impl PlayerResolver {
+    // The cached player list, reloaded when it is older than max_age
+    async fn players(&self, client: &Client, max_age: Duration) -> Result<Arc<Vec<PlayerEntry>>, String> {
+        if let Some((players, loaded_at)) = self.players.read().await.as_ref()
+            && loaded_at.elapsed() < max_age
+        {
+            return Ok(players.clone());
+        }
+        self.refresh(client).await
+    }
+
+    async fn refresh(&self, client: &Client) -> Result<Arc<Vec<PlayerEntry>>, String> {
+        let rows = client
+            .query("SELECT DISTINCT player_id, player FROM player_box_scores", &[])
+            .await
+            .map_err(|e| format!("Player list query error: {}", e))?;
+
+        let players: Arc<Vec<PlayerEntry>> = Arc::new(
+            rows.iter()
+                .map(|row| {
+                    let player: String = row.get(1);
+                    PlayerEntry {
+                        player_id: row.get(0),
+                        normalized: normalize(&player),
+                        player,
+                    }
+                })
+                .collect(),
+        );
+
+        println!("Loaded {} distinct players for name resolution", players.len());
+
+        *self.players.write().await = Some((players.clone(), Instant::now()));
+        Ok(players)
+    }
+
+    pub async fn resolve(&self, client: &Client, name: &str) -> Result<Vec<PlayerEntry>, String> {
+        let matches = match_players(&self.players(client, CACHE_TTL).await?, name);
+        if !matches.is_empty() {
+            return Ok(matches);
+        }
+        Ok(match_players(&self.players(client, MISS_RELOAD_AFTER).await?, name))
+    }
+
+    // Replaces the substring player filter with the ids of the matched players, and returns
+    // the candidates when a name matched several different players. Names that match nobody
+    // keep every name on the ILIKE fallback.
+    pub async fn apply(&self, client: &Client, params: &mut QueryParams) -> Result<Vec<String>, String> {
+        if let Some(filter) = &mut params.filter {
+            self.apply_filter(client, filter).await?;
+        }
+
+        let Some(player) = params.player.clone() else {
+            return Ok(Vec::new());
+        };
+
+        let mut ids: Vec<String> = Vec::new();
+        let mut resolved: Vec<String> = Vec::new();
+        let mut ambiguous: Vec<String> = Vec::new();
+        let mut all_matched = true;
+        for name in player.values() {
+            let matches = self.resolve(client, name).await?;
+            if matches.is_empty() {
+                all_matched = false;
+                resolved.push(name.to_string());
+                continue;
+            }
+
+            let mut names: Vec<String> = Vec::new();
+            for entry in &matches {
+                if !names.contains(&entry.player) {
+                    names.push(entry.player.clone());
+                }
+            }
+
+            println!("Resolved player '{}' to {:?}", name, names);
+
+            if names.len() == 1 {
+                resolved.push(names[0].clone());
+            } else {
+                resolved.push(name.to_string());
+                if ambiguous.is_empty() {
+                    ambiguous = names;
+                }
+            }
+            ids.extend(matches.into_iter().map(|p| p.player_id));
+        }
+
+        params.player = Some(match resolved.len() {
+            1 => ValueList::One(resolved.remove(0)),
+            _ => ValueList::Many(resolved),
+        });
+        if all_matched {
+            params.resolved_player_ids = Some(ids);
+        }
+
+        Ok(ambiguous)
+    }
+}
+
+impl PlayerResolver {
+    // Maps the names in exact player conditions of a filter tree to player ids, like the
+    // player filter. A name matching several players keeps all of them; a condition with a
+    // name that matches nobody keeps comparing names.
+    async fn apply_filter(&self, client: &Client, filter: &mut Filter) -> Result<(), String> {
+        for condition in filter.player_conditions() {
+            let names: Vec<String> = condition
+                .values()
+                .iter()
+                .filter_map(|value| value.as_str().map(str::to_string))
+                .collect();
+            let mut ids: Vec<String> = Vec::new();
+            let mut all_matched = true;
+            for name in &names {
+                let matches = self.resolve(client, name).await?;
+                all_matched &= !matches.is_empty();
+                ids.extend(matches.into_iter().map(|p| p.player_id));
+            }
+            if all_matched && !ids.is_empty() {
+                println!("Resolved filter players {:?} to {} ids", names, ids.len());
+                condition.resolved_player_ids = Some(ids);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Players whose name is the best match for name: exact matches after normalizing and
+// nickname expansion, otherwise every fuzzy match within TIE_WINDOW of the best one
+fn match_players(players: &[PlayerEntry], name: &str) -> Vec<PlayerEntry> {
+    let mut query = normalize(name);
+    if let Some((_, full)) = NICKNAMES.iter().find(|(nick, _)| *nick == query) {
+        query = normalize(full);
+    }
+
+    if query.is_empty() {
+        return Vec::new();
+    }
+
+    let exact: Vec<PlayerEntry> = players
+        .iter()
+        .filter(|p| p.normalized == query)
+        .cloned()
+        .collect();
+    if !exact.is_empty() {
+        return exact;
+    }
+
+    let scored: Vec<(f64, &PlayerEntry)> = players
+        .iter()
+        .map(|p| (score(&query, &p.normalized), p))
+        .filter(|(s, _)| *s >= MATCH_THRESHOLD)
+        .collect();
+
+    let best = scored.iter().map(|(s, _)| *s).fold(0.0, f64::max);
+
+    scored
+        .into_iter()
+        .filter(|(s, _)| best - *s <= TIE_WINDOW)
+        .map(|(_, p)| p.clone())
+        .collect()
+}
+
+fn normalize(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for c in name.chars().flat_map(char::to_lowercase) {
+        let c = match c {
+            'á' | 'à' | 'â' | 'ä' | 'ã' | 'å' => 'a',
+            'ć' | 'č' | 'ç' => 'c',
+            'đ' => 'd',
+            'é' | 'è' | 'ê' | 'ë' | 'ė' => 'e',
+            'í' | 'ì' | 'î' | 'ï' => 'i',
+            'ñ' | 'ń' => 'n',
+            'ó' | 'ò' | 'ô' | 'ö' | 'õ' | 'ø' => 'o',
+            'š' | 'ś' => 's',
+            'ú' | 'ù' | 'û' | 'ü' | 'ū' => 'u',
+            'ý' | 'ÿ' => 'y',
+            'ž' | 'ź' | 'ż' => 'z',
+            '-' => ' ',
+            c => c,
+        };
+        if c.is_alphanumeric() || c == ' ' {
+            out.push(c);
+        }
+    }
+    out.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn score(query: &str, name: &str) -> f64 {
+    trigram_similarity(query, name).max(token_similarity(query, name))
+}
+
+// Same padding scheme as pg_trgm: each word becomes "  word " before splitting
+fn trigrams(s: &str) -> HashSet<[char; 3]> {
+    let mut set = HashSet::new();
+    for word in s.split_whitespace() {
+        let padded: Vec<char> = "  ".chars().chain(word.chars()).chain(" ".chars()).collect();
+        for w in padded.windows(3) {
+            set.insert([w[0], w[1], w[2]]);
+        }
+    }
+    set
+}
+
+fn trigram_similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (trigrams(a), trigrams(b));
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(&b).count() as f64 / union as f64
+}
+
+// Average over query tokens of the best edit-distance similarity against the name's tokens
+fn token_similarity(query: &str, name: &str) -> f64 {
+    let name_tokens: Vec<&str> = name.split_whitespace().collect();
+    let query_tokens: Vec<&str> = query.split_whitespace().collect();
+    if query_tokens.is_empty() || query_tokens.len() > name_tokens.len() {
+        return 0.0;
+    }
+
+    let total: f64 = query_tokens
+        .iter()
+        .map(|q| {
+            name_tokens
+                .iter()
+                .map(|n| {
+                    let max_len = q.chars().count().max(n.chars().count());
+                    1.0 - edit_distance(q, n) as f64 / max_len as f64
+                })
+                .fold(0.0, f64::max)
+        })
+        .sum();
+
+    total / query_tokens.len() as f64
+}
+
+// Optimal string alignment distance, so a swapped pair of letters counts as one edit
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roster(names: &[&str]) -> Vec<PlayerEntry> {
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| PlayerEntry {
+                player_id: format!("p{}", i),
+                player: name.to_string(),
+                normalized: normalize(name),
+            })
+            .collect()
+    }
+
+    fn matched(players: &[PlayerEntry], name: &str) -> Vec<String> {
+        match_players(players, name).into_iter().map(|p| p.player).collect()
+    }
+
+    #[test]
+    fn normalizes_accents_case_and_punctuation() {
+        assert_eq!(normalize("Nikola Jokić"), "nikola jokic");
+        assert_eq!(normalize("  Shai  Gilgeous-Alexander "), "shai gilgeous alexander");
+        assert_eq!(normalize("Shaquille O'Neal"), "shaquille oneal");
+        assert_eq!(normalize("Luka Dončić"), "luka doncic");
+        assert_eq!(normalize("!!"), "");
+    }
+
+    #[test]
+    fn edit_distance_counts_swaps_as_one_edit() {
+        assert_eq!(edit_distance("curry", "curry"), 0);
+        assert_eq!(edit_distance("curry", "cury"), 1);
+        assert_eq!(edit_distance("lebron", "lebrno"), 1);
+        assert_eq!(edit_distance("james", "jmaes"), 1);
+        assert_eq!(edit_distance("", "kd"), 2);
+        assert_eq!(edit_distance("durant", "curry"), 4);
+    }
+
+    #[test]
+    fn trigram_similarity_is_one_for_equal_names_and_zero_for_disjoint() {
+        assert_eq!(trigram_similarity("lebron james", "lebron james"), 1.0);
+        assert_eq!(trigram_similarity("abc", "xyz"), 0.0);
+        assert_eq!(trigram_similarity("", ""), 0.0);
+        let close = trigram_similarity("lebron jame", "lebron james");
+        assert!(close > 0.6 && close < 1.0, "{}", close);
+    }
+
+    #[test]
+    fn token_similarity_matches_each_query_word_to_its_best_name_word() {
+        assert_eq!(token_similarity("curry", "stephen curry"), 1.0);
+        assert_eq!(token_similarity("stephen curry", "curry"), 0.0);
+        assert_eq!(token_similarity("", "stephen curry"), 0.0);
+        // One edit in a five letter word
+        assert!((token_similarity("cury", "stephen curry") - 0.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn resolves_nicknames() {
+        let players = roster(&["LeBron James", "Kevin Durant", "Nikola Jokić", "Anthony Davis"]);
+        assert_eq!(matched(&players, "KD"), ["Kevin Durant"]);
+        assert_eq!(matched(&players, "lbj"), ["LeBron James"]);
+        assert_eq!(matched(&players, "The Brow"), ["Anthony Davis"]);
+        assert_eq!(matched(&players, "joker"), ["Nikola Jokić"]);
+    }
+
+    #[test]
+    fn tolerates_typos() {
+        let players = roster(&["LeBron James", "Giannis Antetokounmpo", "Stephen Curry", "Kevin Durant"]);
+        assert_eq!(matched(&players, "Lebrn James"), ["LeBron James"]);
+        assert_eq!(matched(&players, "giannis antetokoumpo"), ["Giannis Antetokounmpo"]);
+        assert_eq!(matched(&players, "Steph Cury"), ["Stephen Curry"]);
+        assert_eq!(matched(&players, "durnat"), ["Kevin Durant"]);
+    }
+
+    #[test]
+    fn exact_match_wins_over_similar_names() {
+        let players = roster(&["Anthony Davis", "Anthony Davis Jr", "Terence Davis"]);
+        assert_eq!(matched(&players, "anthony davis"), ["Anthony Davis"]);
+    }
+
+    #[test]
+    fn returns_every_player_tied_for_the_best_score() {
+        let players = roster(&["Stephen Curry", "Seth Curry", "Kevin Durant"]);
+        assert_eq!(matched(&players, "Curry"), ["Stephen Curry", "Seth Curry"]);
+        let players = roster(&["Anthony Davis", "Terence Davis"]);
+        assert_eq!(matched(&players, "davis"), ["Anthony Davis", "Terence Davis"]);
+    }
+
+    #[test]
+    fn rejects_names_below_the_threshold() {
+        let players = roster(&["LeBron James", "Kevin Durant"]);
+        assert!(matched(&players, "Michael Jordan").is_empty());
+        assert!(matched(&players, "").is_empty());
+        assert!(matched(&players, "xyz").is_empty());
+    }
+}
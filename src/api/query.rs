@@ -1,70 +1,254 @@
-use axum::{extract::State, http::StatusCode, Json};
-use serde::Deserialize;
-use serde_json::json;
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio_postgres::Client as PgClient;
 
-use crate::llm::{LLMProvider, QUERY_PROMPT};
-use super::boxscores::models::{PaginatedResponse, QueryParams};
-use super::db::query_boxscores;
+use crate::llm::{PromptRegistry, ProviderChain, PromptTemplate, QUERY_PROMPT_ID};
+use super::boxscores::models::{PaginatedResponse, QueryParams, ResponseMeta, ValueList};
+use super::boxscores::schema::{field_docs, llm_query_schema};
+use super::catalog::SchemaCatalog;
+use super::db::{boxscores_sql, query_boxscores};
+use super::examples::{render_examples, ExampleMode, ExampleStore, EXAMPLE_COUNT};
+use super::explain::{explain_params, query_plan};
+use super::guard::{check_input, classifier_enabled, classify, delimit, log_blocked, USER_CONTENT_NOTICE};
+use super::players::PlayerResolver;
+use super::rules::parse_query;
+use super::usage::{api_key_label, check_admin, record_response_usage};
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryMode {
+    #[default]
+    Llm,
+    Rules,
+}
 
 #[derive(Deserialize)]
 pub struct QueryRequest {
     pub query: String,
+    #[serde(default)]
+    pub mode: QueryMode,
+    pub prompt_version: Option<String>,
+    // Describe how the query was interpreted; admins also get the Postgres plan
+    #[serde(default)]
+    pub explain: bool,
+}
+
+#[derive(Deserialize)]
+struct LlmQueryOutput {
+    #[serde(default)]
+    needs_clarification: bool,
+    clarification_question: Option<String>,
+    #[serde(default)]
+    candidates: Vec<String>,
+    #[serde(flatten)]
+    params: QueryParams,
+}
+
+#[derive(Serialize)]
+pub struct ClarificationResponse {
+    pub needs_clarification: bool,
+    pub question: String,
+    pub candidates: Vec<String>,
+    pub query_params: QueryParams,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<ResponseMeta>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum QueryResponse {
+    Results(PaginatedResponse),
+    Clarification(ClarificationResponse),
 }
 
 pub struct AppState {
-    pub llm_provider: LLMProvider,
+    pub llm_provider: Option<ProviderChain>,
     pub db_client: Arc<PgClient>,
     pub readonly_db_client: Arc<PgClient>,
+    pub player_resolver: Arc<PlayerResolver>,
+    pub schema_catalog: Arc<SchemaCatalog>,
+    pub examples: Arc<ExampleStore>,
+    pub prompts: PromptRegistry,
+}
+
+// The query prompt documents the parameters generated from QueryParams and shows
+// verified answers to similar questions
+pub fn query_system_prompt(prompt: &PromptTemplate, examples: &str) -> String {
+    let text = prompt.render(&[("fields", &field_docs()), ("examples", examples)]);
+    format!("{}\n\n{}", text, USER_CONTENT_NOTICE)
+}
+
+fn parse_llm_query_output(response: &str) -> Result<LlmQueryOutput, String> {
+    serde_json::from_str(response.trim())
+        .map_err(|e| format!("Failed to parse JSON: {} | Response: {}", e, response))
+}
+
+fn rules_query_output(query: &str) -> LlmQueryOutput {
+    let params = parse_query(query);
+    println!("Rule-based params: {}", serde_json::to_string(&params).unwrap_or_default());
+
+    LlmQueryOutput {
+        needs_clarification: false,
+        clarification_question: None,
+        candidates: Vec::new(),
+        params,
+    }
 }
 
 pub async fn post_query(
     State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
     Json(req): Json<QueryRequest>,
-) -> Result<Json<PaginatedResponse>, (StatusCode, String)> {
-    let schema = json!({
-        "type": "object",
-        "properties": {
-            "reasoning": {"type": "string", "description": "Explain your reasoning for extracting these parameters from the query"},
-            "player": {"type": "string", "description": "Player name"},
-            "team": {"type": "string", "description": "Team abbreviation (e.g., 'LAL', 'GSW')"},
-            "season": {"type": "string", "description": "Season in format '2024-25'"},
-            "game_date": {"type": "string", "description": "Specific game date in YYYY-MM-DD format"},
-            "pts": {"type": "integer", "description": "Minimum points"},
-            "reb": {"type": "integer", "description": "Minimum rebounds"},
-            "ast": {"type": "integer", "description": "Minimum assists"},
-            "stl": {"type": "integer", "description": "Minimum steals"},
-            "blk": {"type": "integer", "description": "Minimum blocks"},
-            "fg_percent": {"type": "number", "description": "Minimum field goal percentage"},
-            "three_pm": {"type": "integer", "description": "Minimum three-pointers made"},
-            "limit": {"type": "integer", "description": "Max number of results"},
-            "sort_by": {
-                "type": "string",
-                "description": "Field to sort by",
-                "enum": ["pts", "reb", "ast", "stl", "blk", "fg_percent", "three_pm", "game_date"]
-            },
-            "asc": {"type": "boolean", "description": "Sort ascending or descending"}
-        },
-        "required": ["reasoning"]
-    });
-
+) -> Result<Json<QueryResponse>, (StatusCode, String)> {
     println!("User query: {}", req.query);
 
-    let response = state
-        .llm_provider
-        .prompt_with_schema(QUERY_PROMPT, &req.query, schema)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let api_key = api_key_label(&headers);
+    if let Err(e) = check_input(&req.query) {
+        log_blocked("query", &api_key, &e, &req.query);
+        return Err((StatusCode::BAD_REQUEST, e));
+    }
+
+    let mut meta = None;
+    let output = match (req.mode, state.llm_provider.as_ref()) {
+        (QueryMode::Llm, Some(llm_provider)) => {
+            let prompt = state
+                .prompts
+                .select(QUERY_PROMPT_ID, req.prompt_version.as_deref(), &req.query)
+                .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+            let examples = state
+                .examples
+                .similar(ExampleMode::Query, &req.query, EXAMPLE_COUNT)
+                .await;
+            let system_prompt = query_system_prompt(prompt, &render_examples(&examples));
+
+            if classifier_enabled() {
+                let (verdict, response) = classify(llm_provider, &req.query).await;
+                if let Some(mut response) = response {
+                    record_response_usage(state.db_client.clone(), "guard", api_key.clone(), &mut response);
+                }
+                if let Err(e) = verdict {
+                    log_blocked("query", &api_key, &e, &req.query);
+                    return Err((StatusCode::BAD_REQUEST, e));
+                }
+            }
+
+            match llm_provider
+                .prompt_with_schema(&system_prompt, &delimit(&req.query), llm_query_schema())
+                .await
+            {
+                Ok(mut response) => {
+                    println!(
+                        "LLM response [{}@{}] ({} in / {} out tokens, {}ms): {}",
+                        prompt.id,
+                        prompt.version,
+                        response.usage.input_tokens,
+                        response.usage.output_tokens,
+                        response.usage.latency_ms,
+                        response.text
+                    );
+                    record_response_usage(state.db_client.clone(), "query", api_key.clone(), &mut response);
+
+                    // Tokens were spent either way, so usage is reported even when the output is unusable
+                    let mut response_meta = ResponseMeta {
+                        prompt: None,
+                        llm: Some(response.usage),
+                        explanation: None,
+                    };
+                    let output = match parse_llm_query_output(&response.text) {
+                        Ok(output) => {
+                            response_meta.prompt = Some(prompt.reference());
+                            output
+                        }
+                        Err(e) => {
+                            println!("LLM query parsing failed, falling back to rules: {}", e);
+                            rules_query_output(&req.query)
+                        }
+                    };
+                    meta = Some(response_meta);
+                    output
+                }
+                Err(e) => {
+                    println!("LLM query parsing failed, falling back to rules: {}", e);
+                    rules_query_output(&req.query)
+                }
+            }
+        }
+        _ => rules_query_output(&req.query),
+    };
+
+    let mut params = output.params;
+
+    params
+        .normalize()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let mut candidates: Vec<String> = Vec::new();
+    if output.needs_clarification {
+        for candidate in &output.candidates {
+            let matches = state
+                .player_resolver
+                .resolve(&state.db_client, candidate)
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+            for entry in matches {
+                if !candidates.contains(&entry.player) {
+                    candidates.push(entry.player);
+                }
+            }
+        }
+        println!("Clarification candidates: {:?} | Matched players: {:?}", output.candidates, candidates);
+
+        if candidates.len() == 1 {
+            params.player = candidates.pop().map(ValueList::from);
+        }
+    }
+
+    if candidates.len() <= 1 {
+        // The resolver itself can still find several equally good matches (e.g. "Curry")
+        candidates = state
+            .player_resolver
+            .apply(&state.db_client, &mut params)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    }
+
+    if candidates.len() > 1 {
+        let question = output
+            .clarification_question
+            .unwrap_or_else(|| "Which player did you mean?".to_string());
+
+        params.resolved_player_ids = None;
 
-    println!("LLM response: {}", response);
+        return Ok(Json(QueryResponse::Clarification(ClarificationResponse {
+            needs_clarification: true,
+            question,
+            candidates,
+            query_params: params,
+            meta,
+        })));
+    }
 
-    let params: QueryParams = serde_json::from_str(response.trim())
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to parse JSON: {} | Response: {}", e, response)))?;
+    if req.explain {
+        let mut explanation = explain_params(&params);
+        if check_admin(&headers).is_ok() {
+            match query_plan(&state.readonly_db_client, &boxscores_sql(&params)).await {
+                Ok(plan) => explanation.plan = Some(plan),
+                Err(e) => eprintln!("Query plan failed: {}", e),
+            }
+        }
+        meta.get_or_insert_with(ResponseMeta::default).explanation = Some(explanation);
+    }
 
-    let box_scores = query_boxscores(&state.db_client, params)
+    let mut box_scores = query_boxscores(&state.db_client, params)
         .await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    box_scores.meta = meta;
 
-    Ok(Json(box_scores))
+    Ok(Json(QueryResponse::Results(box_scores)))
 }
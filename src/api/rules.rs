@@ -0,0 +1,478 @@
+use super::boxscores::filters::{CompareOp, Condition, ConditionField, Filter, FilterGroup};
+use super::boxscores::models::{QueryParams, SortExpression, SortField, ValueList};
+use super::seasons::{parse_season, SeasonType};
+use super::teams::resolve_team;
+
+// Words that never belong to a player name
+const STOPWORDS: &[&str] = &[
+    "a", "all", "an", "and", "any", "at", "best", "by", "career", "did", "do", "during", "find", "for",
+    "from", "game", "games", "get", "had", "has", "have", "he", "her", "his", "in", "is", "least", "list",
+    "me", "more", "most", "night", "nights", "of", "on", "or", "over", "performance", "performances",
+    "plus", "season", "show", "than", "that", "the", "their", "top", "what", "when", "where", "which",
+    "with", "scoring", "highest", "recent", "latest", "oldest", "earliest", "first", "last", "give",
+    "biggest", "great", "greatest", "was", "were",
+];
+
+const STATS: &[(&str, SortField)] = &[
+    ("points", SortField::Pts),
+    ("point", SortField::Pts),
+    ("pts", SortField::Pts),
+    ("rebounds", SortField::Reb),
+    ("rebound", SortField::Reb),
+    ("reb", SortField::Reb),
+    ("rebs", SortField::Reb),
+    ("boards", SortField::Reb),
+    ("assists", SortField::Ast),
+    ("assist", SortField::Ast),
+    ("ast", SortField::Ast),
+    ("dimes", SortField::Ast),
+    ("steals", SortField::Stl),
+    ("steal", SortField::Stl),
+    ("stl", SortField::Stl),
+    ("blocks", SortField::Blk),
+    ("block", SortField::Blk),
+    ("blk", SortField::Blk),
+    ("threes", SortField::ThreePm),
+    ("3s", SortField::ThreePm),
+    ("3pm", SortField::ThreePm),
+    ("3-pointers", SortField::ThreePm),
+    ("three-pointers", SortField::ThreePm),
+    ("turnovers", SortField::Tov),
+    ("tov", SortField::Tov),
+    ("minutes", SortField::Min),
+    ("mins", SortField::Min),
+    ("min", SortField::Min),
+    ("fouls", SortField::Pf),
+];
+
+struct Token {
+    word: String,
+    original: String,
+}
+
+// "&" and a trailing "," become "and" tokens, so "Curry, Durant & Booker" lists three names
+fn tokenize(text: &str) -> Vec<Token> {
+    let separator = |original: &str| Token {
+        word: "and".to_string(),
+        original: original.to_string(),
+    };
+    let mut tokens = Vec::new();
+    for raw in text.split_whitespace() {
+        if raw == "&" {
+            tokens.push(separator(raw));
+            continue;
+        }
+        let trimmed = raw.trim_matches(|c: char| !c.is_alphanumeric() && c != '+' && c != '-');
+        let trimmed = trimmed
+            .strip_suffix("'s")
+            .or_else(|| trimmed.strip_suffix("’s"))
+            .unwrap_or(trimmed);
+        if !trimmed.is_empty() {
+            tokens.push(Token {
+                word: trimmed.to_lowercase(),
+                original: trimmed.to_string(),
+            });
+        }
+        if raw.ends_with(',') {
+            tokens.push(separator(","));
+        }
+    }
+    tokens
+}
+
+fn stat_for(word: &str) -> Option<SortField> {
+    STATS.iter().find(|(w, _)| *w == word).map(|(_, f)| f.clone())
+}
+
+fn set_min_stat(params: &mut QueryParams, field: &SortField, value: i32) {
+    let slot = match field {
+        SortField::Pts => &mut params.pts,
+        SortField::Reb => &mut params.reb,
+        SortField::Ast => &mut params.ast,
+        SortField::Stl => &mut params.stl,
+        SortField::Blk => &mut params.blk,
+        SortField::ThreePm => &mut params.three_pm,
+        SortField::Tov => &mut params.tov,
+        SortField::Min => &mut params.min,
+        SortField::Pf => &mut params.pf,
+        _ => return,
+    };
+    *slot = Some(value);
+}
+
+// Deterministic parser for the common query shapes, used when no LLM is available or
+// when the caller asks for mode=rules. Anything it does not recognise is treated as
+// part of the player name and left for the player resolver.
+pub fn parse_query(text: &str) -> QueryParams {
+    let tokens = tokenize(text);
+    let mut params = QueryParams::default();
+    let mut used = vec![false; tokens.len()];
+    let mut first_stat: Option<SortField> = None;
+
+    let word = |i: usize| tokens.get(i).map(|t| t.word.as_str()).unwrap_or("");
+
+    let mut i = 0;
+    while i < tokens.len() {
+        let w = word(i);
+
+        // "triple double", "triple-double", "triple doubles"
+        if w.starts_with("triple") && (w.contains("double") || word(i + 1).starts_with("double")) {
+            params.pts = Some(10);
+            params.reb = Some(10);
+            params.ast = Some(10);
+            used[i] = true;
+            if !w.contains("double") {
+                used[i + 1] = true;
+                i += 1;
+            }
+            i += 1;
+            continue;
+        }
+
+        // "top 5", "best 10", "first 3", "last 5"
+        if matches!(w, "top" | "best" | "first" | "last")
+            && let Ok(n) = word(i + 1).parse::<i64>()
+        {
+            params.limit = Some(n);
+            if w == "last" {
+                params.sort.sort_by = Some(SortExpression::Field(SortField::GameDate).into());
+            }
+            used[i] = true;
+            used[i + 1] = true;
+            i += 2;
+            continue;
+        }
+
+        // Seasons: "2023-24", "this season", "last season", "in 2024"
+        if (w == "this" || w == "last") && word(i + 1) == "season" {
+            if let Ok(season) = parse_season(&format!("{} season", w)) {
+                params.season = Some(season);
+                used[i] = true;
+                used[i + 1] = true;
+            }
+            i += 2;
+            continue;
+        }
+        if w.contains('-') && w.chars().next().is_some_and(|c| c.is_ascii_digit())
+            && let Ok(season) = parse_season(w)
+        {
+            params.season = Some(season);
+            used[i] = true;
+            i += 1;
+            continue;
+        }
+        if matches!(w, "in" | "during" | "since")
+            && word(i + 1).len() == 4
+            && let Ok(season) = parse_season(word(i + 1))
+        {
+            if w == "since" {
+                params.season_from = Some(season);
+            } else {
+                params.season = Some(season);
+            }
+            used[i] = true;
+            used[i + 1] = true;
+            i += 2;
+            continue;
+        }
+
+        if w == "playoffs" || w == "playoff" || w == "postseason" {
+            params.season_type = Some(SeasonType::Playoffs);
+            used[i] = true;
+            i += 1;
+            continue;
+        }
+        if w == "regular" && word(i + 1) == "season" {
+            params.season_type = Some(SeasonType::RegularSeason);
+            used[i] = true;
+            used[i + 1] = true;
+            i += 2;
+            continue;
+        }
+
+        // "no turnovers", "zero fouls"
+        if matches!(w, "no" | "zero")
+            && let Some(field) = stat_for(word(i + 1))
+        {
+            add_condition(&mut params, Condition {
+                field: ConditionField::Expression(SortExpression::Field(field)),
+                op: CompareOp::Eq,
+                value: 0.into(),
+                resolved_player_ids: None,
+            });
+            used[i] = true;
+            used[i + 1] = true;
+            i += 2;
+            continue;
+        }
+
+        // "30+ points", "30 points", "30 or more points", "at least 30 points", "over 30 points"
+        let (number, bonus) = if matches!(w, "over" | "above") {
+            (word(i + 1).trim_end_matches('+').parse::<i32>().ok(), 1)
+        } else if w == "more" && word(i + 1) == "than" {
+            (word(i + 2).trim_end_matches('+').parse::<i32>().ok(), 1)
+        } else {
+            (w.trim_end_matches('+').parse::<i32>().ok(), 0)
+        };
+        if let Some(n) = number {
+            let mut j = i + match (w, bonus) {
+                ("more", _) => 3,
+                (_, 1) => 2,
+                _ => 1,
+            };
+            if word(j) == "or" && matches!(word(j + 1), "more" | "better") {
+                j += 2;
+            }
+            if word(j) == "+" || word(j) == "plus" {
+                j += 1;
+            }
+            if let Some(field) = stat_for(word(j)) {
+                // "over 2147483647 points" clamps instead of overflowing
+                set_min_stat(&mut params, &field, n.checked_add(bonus).unwrap_or(i32::MAX));
+                first_stat.get_or_insert(field);
+                for u in used.iter_mut().take(j + 1).skip(i) {
+                    *u = true;
+                }
+                i = j + 1;
+                continue;
+            }
+            if matches!(word(j), "games" | "game" | "performances" | "nights") && bonus == 0 {
+                params.limit = Some(n as i64);
+                for u in used.iter_mut().take(j + 1).skip(i) {
+                    *u = true;
+                }
+                i = j + 1;
+                continue;
+            }
+        }
+
+        // "most assists", "highest scoring", "most recent"
+        if matches!(w, "most" | "highest" | "biggest") {
+            let next = word(i + 1);
+            let field = if next == "recent" || next == "recently" {
+                Some(SortField::GameDate)
+            } else if next == "scoring" {
+                Some(SortField::Pts)
+            } else {
+                stat_for(next)
+            };
+            if let Some(field) = field {
+                params.sort.sort_by = Some(SortExpression::Field(field).into());
+                params.sort.asc = Some(false);
+                used[i] = true;
+                used[i + 1] = true;
+                i += 2;
+                continue;
+            }
+        }
+        if matches!(w, "latest" | "recent") {
+            params.sort.sort_by = Some(SortExpression::Field(SortField::GameDate).into());
+            params.sort.asc = Some(false);
+        }
+        if matches!(w, "oldest" | "earliest") {
+            params.sort.sort_by = Some(SortExpression::Field(SortField::GameDate).into());
+            params.sort.asc = Some(true);
+        }
+
+        // "vs Boston", "against the Celtics", "for the Lakers"
+        if matches!(w, "vs" | "vs." | "versus" | "against" | "for" | "with") {
+            let start = if word(i + 1) == "the" { i + 2 } else { i + 1 };
+            if let Some(end) = match_team(&tokens, start) {
+                let name = join_original(&tokens[start..end]);
+                if matches!(w, "for" | "with") {
+                    params.team = Some(name.into());
+                } else {
+                    params.opponent = Some(name);
+                }
+                for u in used.iter_mut().take(end).skip(i) {
+                    *u = true;
+                }
+                i = end;
+                continue;
+            }
+        }
+
+        i += 1;
+    }
+
+    // A bare team name ("Lakers games with 40 points") is the player's team, as long as
+    // it is not part of a longer name ("Magic Johnson")
+    let is_free = |k: usize| {
+        k < tokens.len() && !used[k] && !STOPWORDS.contains(&word(k)) && stat_for(word(k)).is_none()
+    };
+    if params.team.is_none() {
+        let mut k = 0;
+        while k < tokens.len() {
+            if is_free(k)
+                && (k == 0 || !is_free(k - 1))
+                && let Some(end) = match_team(&tokens, k)
+                && !is_free(end)
+            {
+                params.team = Some(join_original(&tokens[k..end]).into());
+                for u in used.iter_mut().take(end).skip(k) {
+                    *u = true;
+                }
+                break;
+            }
+            k += 1;
+        }
+    }
+
+    // The first run of leftover words is the player; runs joined by "and" list several
+    let mut players: Vec<String> = Vec::new();
+    let mut name: Vec<&str> = Vec::new();
+    for (k, token) in tokens.iter().enumerate() {
+        let leftover = !used[k]
+            && !STOPWORDS.contains(&token.word.as_str())
+            && stat_for(&token.word).is_none()
+            && token.word.parse::<f64>().is_err();
+        if leftover {
+            name.push(token.original.as_str());
+            continue;
+        }
+        if name.is_empty() {
+            if players.is_empty() {
+                continue;
+            }
+            break;
+        }
+        players.push(name.join(" "));
+        name.clear();
+        if used[k] || token.word != "and" {
+            break;
+        }
+    }
+    if !name.is_empty() {
+        players.push(name.join(" "));
+    }
+    params.player = match players.len() {
+        0 => None,
+        1 => Some(players.remove(0).into()),
+        _ => Some(ValueList::Many(players)),
+    };
+
+    if params.sort.sort_by.is_none()
+        && let Some(field) = first_stat
+    {
+        params.sort.sort_by = Some(SortExpression::Field(field).into());
+        params.sort.asc = Some(false);
+    }
+
+    params
+}
+
+// ANDs condition into the filter tree
+fn add_condition(params: &mut QueryParams, condition: Condition) {
+    params.filter = Some(match params.filter.take() {
+        None => Filter::Condition(condition),
+        Some(Filter::Group(FilterGroup::And(mut items))) => {
+            items.push(Filter::Condition(condition));
+            Filter::Group(FilterGroup::And(items))
+        }
+        Some(other) => Filter::Group(FilterGroup::And(vec![other, Filter::Condition(condition)])),
+    });
+}
+
+// Longest run of up to three words starting at `start` that names a team
+fn match_team(tokens: &[Token], start: usize) -> Option<usize> {
+    (1..=3)
+        .rev()
+        .map(|len| start + len)
+        .filter(|end| *end <= tokens.len())
+        .find(|end| resolve_team(&join_original(&tokens[start..*end]), None).is_some())
+}
+
+fn join_original(tokens: &[Token]) -> String {
+    tokens
+        .iter()
+        .map(|t| t.original.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::seasons::current_season_start;
+    use serde_json::{json, Value};
+
+    // The params parse_query sets, without the ones left empty
+    fn parsed(text: &str) -> Value {
+        let params = serde_json::to_value(parse_query(text)).unwrap();
+        let set = params
+            .as_object()
+            .unwrap()
+            .iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        Value::Object(set)
+    }
+
+    fn check(cases: &[(&str, Value)]) {
+        for (text, expected) in cases {
+            assert_eq!(&parsed(text), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn stats() {
+        check(&[
+            ("30 points", json!({"pts": 30, "sort_by": "pts", "asc": false})),
+            ("30+ points", json!({"pts": 30, "sort_by": "pts", "asc": false})),
+            ("30 or more points", json!({"pts": 30, "sort_by": "pts", "asc": false})),
+            ("over 30 points", json!({"pts": 31, "sort_by": "pts", "asc": false})),
+            ("more than 5 steals", json!({"stl": 6, "sort_by": "stl", "asc": false})),
+            ("over 2147483647 points", json!({"pts": 2147483647, "sort_by": "pts", "asc": false})),
+            ("more than 2147483647 assists", json!({"ast": 2147483647, "sort_by": "ast", "asc": false})),
+            ("15 rebounds 10 assists", json!({"reb": 15, "ast": 10, "sort_by": "reb", "asc": false})),
+            ("triple doubles", json!({"pts": 10, "reb": 10, "ast": 10})),
+            ("most assists", json!({"sort_by": "ast", "asc": false})),
+            ("highest scoring games", json!({"sort_by": "pts", "asc": false})),
+            ("top 5 games", json!({"limit": 5})),
+            ("games with no turnovers", json!({"filter": {"field": "tov", "op": "eq", "value": 0}})),
+        ]);
+    }
+
+    #[test]
+    fn teams_and_opponents() {
+        check(&[
+            ("games vs the Celtics", json!({"opponent": "Celtics"})),
+            ("games against Miami", json!({"opponent": "Miami"})),
+            ("games for the Lakers", json!({"team": "Lakers"})),
+            ("Lakers games with 40 points", json!({"team": "Lakers", "pts": 40, "sort_by": "pts", "asc": false})),
+            ("games vs NO", json!({"opponent": "NO"})),
+        ]);
+    }
+
+    #[test]
+    fn seasons() {
+        let current = current_season_start();
+        check(&[
+            ("games in 2023", json!({"season": "2022-23"})),
+            ("games 2022-23", json!({"season": "2022-23"})),
+            ("games since 2020", json!({"season_from": "2019-20"})),
+            ("games this season", json!({"season": format!("{}-{:02}", current, (current + 1) % 100)})),
+            ("games last season", json!({"season": format!("{}-{:02}", current - 1, current % 100)})),
+            ("playoff games", json!({"season_type": "playoffs"})),
+            ("regular season games", json!({"season_type": "regular_season"})),
+        ]);
+    }
+
+    #[test]
+    fn players() {
+        check(&[
+            ("LeBron James 30 points 10 assists", json!({"player": "LeBron James", "pts": 30, "ast": 10, "sort_by": "pts", "asc": false})),
+            ("Curry games vs the Celtics", json!({"player": "Curry", "opponent": "Celtics"})),
+            ("Tatum for Boston", json!({"player": "Tatum", "team": "Boston"})),
+            ("Magic Johnson 20 assists", json!({"player": "Magic Johnson", "ast": 20, "sort_by": "ast", "asc": false})),
+            ("last 10 Durant games", json!({"player": "Durant", "limit": 10, "sort_by": "game_date"})),
+            ("Jokic playoff games since 2020", json!({"player": "Jokic", "season_type": "playoffs", "season_from": "2019-20"})),
+            ("Curry was great", json!({"player": "Curry"})),
+            ("Curry and Durant 30 points", json!({"player": ["Curry", "Durant"], "pts": 30, "sort_by": "pts", "asc": false})),
+            ("LeBron James, Kevin Durant & Stephen Curry", json!({"player": ["LeBron James", "Kevin Durant", "Stephen Curry"]})),
+            ("Jokic points and rebounds", json!({"player": "Jokic"})),
+            ("Curry and 30 points", json!({"player": "Curry", "pts": 30, "sort_by": "pts", "asc": false})),
+        ]);
+    }
+}
@@ -0,0 +1,203 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+// First season in the BAA/NBA record books
+const FIRST_SEASON: i32 = 1946;
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, ToSchema, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SeasonType {
+    Preseason,
+    #[serde(alias = "regular", alias = "regular season")]
+    RegularSeason,
+    #[serde(alias = "playoff", alias = "postseason")]
+    Playoffs,
+    #[serde(alias = "play-in", alias = "playin")]
+    PlayIn,
+}
+
+impl SeasonType {
+    // NBA game ids start with a prefix encoding the season type, e.g. 0022400001
+    pub fn game_id_prefix(&self) -> &str {
+        match self {
+            SeasonType::Preseason => "001",
+            SeasonType::RegularSeason => "002",
+            SeasonType::Playoffs => "004",
+            SeasonType::PlayIn => "005",
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        match self {
+            SeasonType::Preseason => "preseason",
+            SeasonType::RegularSeason => "regular season",
+            SeasonType::Playoffs => "playoff",
+            SeasonType::PlayIn => "play-in",
+        }
+    }
+}
+
+// Normalizes a season expression to the 'YYYY-YY' format stored in player_box_scores.
+// A bare year is the season that ends in it, the way the NBA names seasons after its Finals.
+pub fn parse_season(input: &str) -> Result<String, String> {
+    let raw = input.trim().to_lowercase();
+    let invalid = || {
+        format!(
+            "Invalid season '{}': expected a season like '2024-25', '2024-2025', '24-25', '2025' (the 2024-25 season), 'this season' or 'last season'",
+            input
+        )
+    };
+
+    let start = match raw.as_str() {
+        "this season" | "current season" | "this year" | "current" => current_season_start(),
+        "last season" | "previous season" | "last year" => current_season_start() - 1,
+        _ => {
+            let parts: Vec<&str> = raw.split(['-', '/']).map(str::trim).collect();
+            match parts.as_slice() {
+                [year] if year.len() == 4 => year.parse::<i32>().map_err(|_| invalid())? - 1,
+                [from, to] => {
+                    let from_year: i32 = from.parse().map_err(|_| invalid())?;
+                    let to_year: i32 = to.parse().map_err(|_| invalid())?;
+                    let start = match from.len() {
+                        4 => from_year,
+                        2 => expand_two_digit_year(from_year),
+                        _ => return Err(invalid()),
+                    };
+                    let end_matches = match to.len() {
+                        4 => to_year == start + 1,
+                        2 => to_year == (start + 1) % 100,
+                        _ => false,
+                    };
+                    if !end_matches {
+                        return Err(format!(
+                            "Invalid season '{}': a season spans two consecutive years, e.g. '{}-{:02}'",
+                            input,
+                            start,
+                            (start + 1) % 100
+                        ));
+                    }
+                    start
+                }
+                _ => return Err(invalid()),
+            }
+        }
+    };
+
+    let current = current_season_start();
+    if start < FIRST_SEASON || start > current {
+        return Err(format!(
+            "Invalid season '{}': seasons range from {}-{:02} to {}-{:02}",
+            input,
+            FIRST_SEASON,
+            (FIRST_SEASON + 1) % 100,
+            current,
+            (current + 1) % 100
+        ));
+    }
+
+    Ok(format!("{}-{:02}", start, (start + 1) % 100))
+}
+
+fn expand_two_digit_year(year: i32) -> i32 {
+    if year >= FIRST_SEASON % 100 { 1900 + year } else { 2000 + year }
+}
+
+// Seasons tip off in October, so before then the current season started the previous year
+pub fn current_season_start() -> i32 {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / 86_400)
+        .unwrap_or(0) as i64;
+    let (year, month) = civil_from_days(days);
+    if month >= 10 { year } else { year - 1 }
+}
+
+// Year and month of a day count since 1970-01-01 (Howard Hinnant's algorithm)
+fn civil_from_days(days: i64) -> (i32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year as i32, month as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn season(start: i32) -> String {
+        format!("{}-{:02}", start, (start + 1) % 100)
+    }
+
+    #[test]
+    fn accepts_every_season_format() {
+        for (input, expected) in [
+            ("2024-25", "2024-25"),
+            ("2024-2025", "2024-25"),
+            ("24-25", "2024-25"),
+            ("2024/25", "2024-25"),
+            (" 2024-25 ", "2024-25"),
+            ("99-00", "1999-00"),
+            ("1999-2000", "1999-00"),
+            ("46-47", "1946-47"),
+            ("1946-47", "1946-47"),
+        ] {
+            assert_eq!(parse_season(input).as_deref(), Ok(expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn bare_year_is_the_season_ending_in_it() {
+        assert_eq!(parse_season("2020").as_deref(), Ok("2019-20"));
+        assert_eq!(parse_season("2000").as_deref(), Ok("1999-00"));
+        assert_eq!(parse_season("1947").as_deref(), Ok("1946-47"));
+    }
+
+    #[test]
+    fn relative_seasons_follow_the_calendar() {
+        let current = current_season_start();
+        assert_eq!(parse_season("this season"), Ok(season(current)));
+        assert_eq!(parse_season("Current Season"), Ok(season(current)));
+        assert_eq!(parse_season("last season"), Ok(season(current - 1)));
+        assert_eq!(parse_season("last year"), Ok(season(current - 1)));
+    }
+
+    #[test]
+    fn rejects_years_that_are_not_consecutive() {
+        for input in ["2024-26", "2024-2026", "24-26", "2024-23"] {
+            let err = parse_season(input).unwrap_err();
+            assert!(err.contains("two consecutive years"), "{}: {}", input, err);
+        }
+    }
+
+    #[test]
+    fn rejects_seasons_outside_the_record_books() {
+        let next = current_season_start() + 1;
+        for input in ["1945-46".to_string(), "1946".to_string(), season(next), (next + 1).to_string()] {
+            let err = parse_season(&input).unwrap_err();
+            assert!(err.contains("seasons range from 1946-47"), "{}: {}", input, err);
+        }
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        for input in ["", "garbage", "20-2", "202", "2024-25-26", "abcd-ef", "next season"] {
+            assert!(parse_season(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn civil_from_days_handles_month_and_leap_year_boundaries() {
+        assert_eq!(civil_from_days(0), (1970, 1));
+        assert_eq!(civil_from_days(59), (1970, 3));
+        // 2024-02-29 and 2024-10-01
+        assert_eq!(civil_from_days(19_782), (2024, 2));
+        assert_eq!(civil_from_days(19_997), (2024, 10));
+    }
+}
@@ -1,26 +1,42 @@
-use axum::{extract::State, http::StatusCode, Json};
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    Json,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::sync::Arc;
 use tokio::time::{timeout, Duration};
-use tokio_postgres::Client;
+use tokio_postgres::{Client, Row};
 
-use crate::llm::SQL_PROMPT;
+use crate::llm::{PromptTemplate, SQL_PROMPT_ID};
+use super::boxscores::models::ResponseMeta;
+use super::examples::{render_examples, ExampleMode, EXAMPLE_COUNT};
+use super::explain::{explain_sql, query_plan};
+use super::export::{export_rows, stream_json, ExportFormat, ExportSource, FormatParams, JsonTail};
+use super::guard::{check_input, check_sql, classifier_enabled, classify, delimit, log_blocked, USER_CONTENT_NOTICE};
 use super::query::AppState;
+use super::usage::{api_key_label, check_admin, record_response_usage};
 
 #[derive(Deserialize)]
 pub struct SqlRequest {
     pub query: String,
+    pub prompt_version: Option<String>,
+    // Describe the generated SQL; admins also get the Postgres plan
+    #[serde(default)]
+    pub explain: bool,
 }
 
+// Fields of the /api/sql response that follow the streamed "data" rows
 #[derive(Serialize)]
-pub struct SqlResponse {
-    pub data: Vec<Value>,
+pub struct SqlResponseTail {
     pub total: usize,
     pub limit: usize,
     pub offset: usize,
     pub explicit_limit: bool,
     pub query_params: Value,
+    pub meta: ResponseMeta,
 }
 
 pub async fn execute_sql_query(
@@ -34,94 +50,190 @@ pub async fn execute_sql_query(
         .map_err(|e| format!("Database error: {}", e))?;
 
     // Convert rows to JSON
-    let mut result_rows = Vec::new();
-    for row in &rows {
-        let mut obj = serde_json::Map::new();
-        for (idx, column) in row.columns().iter().enumerate() {
-            let value: Value = match column.type_().name() {
-                "int4" => {
-                    row.try_get::<_, Option<i32>>(idx)
-                        .unwrap_or(None)
-                        .map(|v| serde_json::json!(v))
-                        .unwrap_or(Value::Null)
-                }
-                "int8" => {
-                    row.try_get::<_, Option<i64>>(idx)
-                        .unwrap_or(None)
-                        .map(|v| serde_json::json!(v))
-                        .unwrap_or(Value::Null)
-                }
-                "float4" | "float8" => {
-                    row.try_get::<_, Option<f64>>(idx)
-                        .unwrap_or(None)
-                        .map(|v| serde_json::json!(v))
-                        .unwrap_or(Value::Null)
-                }
-                "varchar" | "text" => {
-                    row.try_get::<_, Option<String>>(idx)
-                        .unwrap_or(None)
-                        .map(|v| serde_json::json!(v))
-                        .unwrap_or(Value::Null)
-                }
-                "bool" => {
-                    row.try_get::<_, Option<bool>>(idx)
-                        .unwrap_or(None)
-                        .map(|v| serde_json::json!(v))
-                        .unwrap_or(Value::Null)
-                }
-                "timestamp" => {
-                    row.try_get::<_, Option<String>>(idx)
-                        .unwrap_or(None)
-                        .map(|v| serde_json::json!(v))
-                        .unwrap_or(Value::Null)
-                }
-                _ => Value::Null,
-            };
-            obj.insert(column.name().to_string(), value);
+    let result_rows = rows
+        .iter()
+        .map(|row| {
+            let mut obj = serde_json::Map::new();
+            for (idx, column) in row.columns().iter().enumerate() {
+                obj.insert(column.name().to_string(), column_value(row, idx));
+            }
+            Value::Object(obj)
+        })
+        .collect();
+
+    Ok(result_rows)
+}
+
+// Reads a column as JSON. Types without a mapping come back as null.
+pub fn column_value(row: &Row, idx: usize) -> Value {
+    match row.columns()[idx].type_().name() {
+        "int4" => {
+            row.try_get::<_, Option<i32>>(idx)
+                .unwrap_or(None)
+                .map(|v| serde_json::json!(v))
+                .unwrap_or(Value::Null)
+        }
+        "int8" => {
+            row.try_get::<_, Option<i64>>(idx)
+                .unwrap_or(None)
+                .map(|v| serde_json::json!(v))
+                .unwrap_or(Value::Null)
+        }
+        "float4" | "float8" => {
+            row.try_get::<_, Option<f64>>(idx)
+                .unwrap_or(None)
+                .map(|v| serde_json::json!(v))
+                .unwrap_or(Value::Null)
+        }
+        "varchar" | "text" => {
+            row.try_get::<_, Option<String>>(idx)
+                .unwrap_or(None)
+                .map(|v| serde_json::json!(v))
+                .unwrap_or(Value::Null)
+        }
+        "bool" => {
+            row.try_get::<_, Option<bool>>(idx)
+                .unwrap_or(None)
+                .map(|v| serde_json::json!(v))
+                .unwrap_or(Value::Null)
         }
-        result_rows.push(Value::Object(obj));
+        "timestamp" => {
+            row.try_get::<_, Option<String>>(idx)
+                .unwrap_or(None)
+                .map(|v| serde_json::json!(v))
+                .unwrap_or(Value::Null)
+        }
+        _ => Value::Null,
     }
+}
 
-    Ok(result_rows)
+// The SQL prompt lists the columns of the whitelisted views as introspected from the database
+// and shows verified answers to similar questions
+pub fn sql_system_prompt(prompt: &PromptTemplate, schema: &str, examples: &str) -> String {
+    let text = prompt.render(&[("schema", schema), ("examples", examples)]);
+    format!("{}\n\n{}", text, USER_CONTENT_NOTICE)
 }
 
 pub async fn post_sql(
     State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(format): Query<FormatParams>,
     Json(req): Json<SqlRequest>,
-) -> Result<Json<SqlResponse>, (StatusCode, String)> {
+) -> Result<Response, (StatusCode, String)> {
     println!("User query: {}", req.query);
 
+    let format = ExportFormat::negotiate(&headers, format.format.as_deref())?;
+
+    let api_key = api_key_label(&headers);
+    if let Err(e) = check_input(&req.query) {
+        log_blocked("sql", &api_key, &e, &req.query);
+        return Err((StatusCode::BAD_REQUEST, e));
+    }
+
+    let llm_provider = state.llm_provider.as_ref().ok_or((
+        StatusCode::SERVICE_UNAVAILABLE,
+        "No LLM provider configured".to_string(),
+    ))?;
+
+    let prompt = state
+        .prompts
+        .select(SQL_PROMPT_ID, req.prompt_version.as_deref(), &req.query)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let examples = state
+        .examples
+        .similar(ExampleMode::Sql, &req.query, EXAMPLE_COUNT)
+        .await;
+    let system_prompt = sql_system_prompt(
+        prompt,
+        &state.schema_catalog.prompt_text().await,
+        &render_examples(&examples),
+    );
+
+    if classifier_enabled() {
+        let (verdict, response) = classify(llm_provider, &req.query).await;
+        if let Some(mut response) = response {
+            record_response_usage(state.db_client.clone(), "guard", api_key.clone(), &mut response);
+        }
+        if let Err(e) = verdict {
+            log_blocked("sql", &api_key, &e, &req.query);
+            return Err((StatusCode::BAD_REQUEST, e));
+        }
+    }
+
     // Get SQL from LLM
-    let sql = state
-        .llm_provider
-        .prompt(SQL_PROMPT, &req.query)
+    let mut response = llm_provider
+        .prompt(&system_prompt, &delimit(&req.query))
         .await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
-    let sql = sql.trim().to_string();
-
-    println!("Generated SQL: {}", sql);
-
-    // Execute with 10 second timeout
-    let rows = timeout(
-        Duration::from_secs(10),
-        execute_sql_query(&state.readonly_db_client, &sql)
-    )
-    .await
-    .map_err(|_| (
-        StatusCode::REQUEST_TIMEOUT,
-        "Query execution exceeded 10 second timeout".to_string()
-    ))?
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
-
-    let row_count = rows.len();
-
-    Ok(Json(SqlResponse {
-        data: rows,
-        total: row_count,
-        limit: row_count,
-        offset: 0,
-        explicit_limit: true,
-        query_params: serde_json::json!({"sql": sql}),
-    }))
+    record_response_usage(state.db_client.clone(), "sql", api_key.clone(), &mut response);
+
+    let sql = response.text.trim().to_string();
+
+    println!(
+        "Generated SQL [{}@{}] ({} in / {} out tokens, {}ms): {}",
+        prompt.id,
+        prompt.version,
+        response.usage.input_tokens,
+        response.usage.output_tokens,
+        response.usage.latency_ms,
+        sql
+    );
+
+    if let Err(e) = check_sql(&sql) {
+        log_blocked("sql", &api_key, &e, &sql);
+        return Err((StatusCode::BAD_REQUEST, e));
+    }
+
+    let explanation = if req.explain {
+        let mut explanation = explain_sql(&sql).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        if check_admin(&headers).is_ok() {
+            match query_plan(&state.readonly_db_client, &sql.as_str().into()).await {
+                Ok(plan) => explanation.plan = Some(plan),
+                Err(e) => eprintln!("Query plan failed: {}", e),
+            }
+        }
+        Some(explanation)
+    } else {
+        None
+    };
+
+    // Rows are streamed from a cursor in every format. The timeout covers connecting and
+    // planning; each fetch is limited by the cursor's statement timeout.
+    let stream = async {
+        match format {
+            ExportFormat::Json => {
+                let tail = SqlResponseTail {
+                    total: 0,
+                    limit: 0,
+                    offset: 0,
+                    explicit_limit: true,
+                    query_params: serde_json::json!({"sql": sql}),
+                    meta: ResponseMeta {
+                        prompt: Some(prompt.reference()),
+                        llm: Some(response.usage),
+                        explanation,
+                    },
+                };
+                let tail: JsonTail = Box::new(move |rows| {
+                    serde_json::to_value(SqlResponseTail {
+                        total: rows,
+                        limit: rows,
+                        ..tail
+                    })
+                    .unwrap_or_default()
+                });
+                stream_json(ExportSource::ReadOnly, sql.as_str().into(), tail).await
+            }
+            _ => export_rows(ExportSource::ReadOnly, sql.as_str().into(), format, "query").await,
+        }
+    };
+
+    timeout(Duration::from_secs(10), stream)
+        .await
+        .map_err(|_| (
+            StatusCode::REQUEST_TIMEOUT,
+            "Query execution exceeded 10 second timeout".to_string()
+        ))?
 }
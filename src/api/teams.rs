@@ -0,0 +1,167 @@
+// Every name a franchise has played under. Years are season start years, inclusive.
+struct TeamName {
+    abbreviation: &'static str,
+    city: &'static str,
+    nickname: &'static str,
+    aliases: &'static [&'static str],
+    from: i32,
+    to: Option<i32>,
+}
+
+const TEAMS: &[TeamName] = &[
+    TeamName { abbreviation: "ATL", city: "Atlanta", nickname: "Hawks", aliases: &[], from: 1968, to: None },
+    TeamName { abbreviation: "BOS", city: "Boston", nickname: "Celtics", aliases: &["cs"], from: 1946, to: None },
+    TeamName { abbreviation: "BKN", city: "Brooklyn", nickname: "Nets", aliases: &["bkn", "bk"], from: 2012, to: None },
+    TeamName { abbreviation: "NJN", city: "New Jersey", nickname: "Nets", aliases: &["nj"], from: 1977, to: Some(2011) },
+    TeamName { abbreviation: "CHA", city: "Charlotte", nickname: "Hornets", aliases: &[], from: 2014, to: None },
+    TeamName { abbreviation: "CHA", city: "Charlotte", nickname: "Bobcats", aliases: &["cats"], from: 2004, to: Some(2013) },
+    TeamName { abbreviation: "CHH", city: "Charlotte", nickname: "Hornets", aliases: &[], from: 1988, to: Some(2001) },
+    TeamName { abbreviation: "CHI", city: "Chicago", nickname: "Bulls", aliases: &[], from: 1966, to: None },
+    TeamName { abbreviation: "CLE", city: "Cleveland", nickname: "Cavaliers", aliases: &["cavs"], from: 1970, to: None },
+    TeamName { abbreviation: "DAL", city: "Dallas", nickname: "Mavericks", aliases: &["mavs"], from: 1980, to: None },
+    TeamName { abbreviation: "DEN", city: "Denver", nickname: "Nuggets", aliases: &[], from: 1976, to: None },
+    TeamName { abbreviation: "DET", city: "Detroit", nickname: "Pistons", aliases: &[], from: 1957, to: None },
+    TeamName { abbreviation: "GSW", city: "Golden State", nickname: "Warriors", aliases: &["gs", "dubs"], from: 1971, to: None },
+    TeamName { abbreviation: "SFW", city: "San Francisco", nickname: "Warriors", aliases: &[], from: 1962, to: Some(1970) },
+    TeamName { abbreviation: "HOU", city: "Houston", nickname: "Rockets", aliases: &[], from: 1971, to: None },
+    TeamName { abbreviation: "SDR", city: "San Diego", nickname: "Rockets", aliases: &[], from: 1967, to: Some(1970) },
+    TeamName { abbreviation: "IND", city: "Indiana", nickname: "Pacers", aliases: &[], from: 1976, to: None },
+    TeamName { abbreviation: "LAC", city: "Los Angeles", nickname: "Clippers", aliases: &["la clippers", "clips"], from: 1984, to: None },
+    TeamName { abbreviation: "SDC", city: "San Diego", nickname: "Clippers", aliases: &[], from: 1978, to: Some(1983) },
+    TeamName { abbreviation: "BUF", city: "Buffalo", nickname: "Braves", aliases: &[], from: 1970, to: Some(1977) },
+    TeamName { abbreviation: "LAL", city: "Los Angeles", nickname: "Lakers", aliases: &["la lakers"], from: 1960, to: None },
+    TeamName { abbreviation: "MNL", city: "Minneapolis", nickname: "Lakers", aliases: &[], from: 1948, to: Some(1959) },
+    TeamName { abbreviation: "MEM", city: "Memphis", nickname: "Grizzlies", aliases: &["grizz"], from: 2001, to: None },
+    TeamName { abbreviation: "VAN", city: "Vancouver", nickname: "Grizzlies", aliases: &[], from: 1995, to: Some(2000) },
+    TeamName { abbreviation: "MIA", city: "Miami", nickname: "Heat", aliases: &[], from: 1988, to: None },
+    TeamName { abbreviation: "MIL", city: "Milwaukee", nickname: "Bucks", aliases: &[], from: 1968, to: None },
+    TeamName { abbreviation: "MIN", city: "Minnesota", nickname: "Timberwolves", aliases: &["wolves", "t wolves"], from: 1989, to: None },
+    TeamName { abbreviation: "NOP", city: "New Orleans", nickname: "Pelicans", aliases: &["no", "pels"], from: 2013, to: None },
+    TeamName { abbreviation: "NOH", city: "New Orleans", nickname: "Hornets", aliases: &[], from: 2002, to: Some(2012) },
+    TeamName { abbreviation: "NOK", city: "Oklahoma City", nickname: "Hornets", aliases: &["new orleans hornets", "new orleans oklahoma city hornets"], from: 2005, to: Some(2006) },
+    TeamName { abbreviation: "NYK", city: "New York", nickname: "Knicks", aliases: &["ny"], from: 1946, to: None },
+    TeamName { abbreviation: "OKC", city: "Oklahoma City", nickname: "Thunder", aliases: &["okc"], from: 2008, to: None },
+    TeamName { abbreviation: "SEA", city: "Seattle", nickname: "SuperSonics", aliases: &["sonics"], from: 1967, to: Some(2007) },
+    TeamName { abbreviation: "ORL", city: "Orlando", nickname: "Magic", aliases: &[], from: 1989, to: None },
+    TeamName { abbreviation: "PHI", city: "Philadelphia", nickname: "76ers", aliases: &["sixers", "philly"], from: 1963, to: None },
+    TeamName { abbreviation: "SYR", city: "Syracuse", nickname: "Nationals", aliases: &["nats"], from: 1949, to: Some(1962) },
+    TeamName { abbreviation: "PHX", city: "Phoenix", nickname: "Suns", aliases: &["pho"], from: 1968, to: None },
+    TeamName { abbreviation: "POR", city: "Portland", nickname: "Trail Blazers", aliases: &["blazers"], from: 1970, to: None },
+    TeamName { abbreviation: "SAC", city: "Sacramento", nickname: "Kings", aliases: &[], from: 1985, to: None },
+    TeamName { abbreviation: "KCK", city: "Kansas City", nickname: "Kings", aliases: &[], from: 1975, to: Some(1984) },
+    TeamName { abbreviation: "SAS", city: "San Antonio", nickname: "Spurs", aliases: &["sa"], from: 1976, to: None },
+    TeamName { abbreviation: "TOR", city: "Toronto", nickname: "Raptors", aliases: &["raps"], from: 1995, to: None },
+    TeamName { abbreviation: "UTA", city: "Utah", nickname: "Jazz", aliases: &[], from: 1979, to: None },
+    TeamName { abbreviation: "NOJ", city: "New Orleans", nickname: "Jazz", aliases: &[], from: 1974, to: Some(1978) },
+    TeamName { abbreviation: "WAS", city: "Washington", nickname: "Wizards", aliases: &["wsh"], from: 1997, to: None },
+    TeamName { abbreviation: "WAS", city: "Washington", nickname: "Bullets", aliases: &[], from: 1974, to: Some(1996) },
+];
+
+// Short forms that are also ordinary words, or too short to trust in free text ("no
+// turnovers", "he was"). They only count as a team when written in capitals.
+const UPPERCASE_ONLY: &[&str] = &["no", "sa", "ny", "gs", "nj", "bk", "cs", "was", "min"];
+
+impl TeamName {
+    fn matches(&self, name: &str) -> bool {
+        let full = format!("{} {}", self.city, self.nickname);
+        name == self.abbreviation.to_lowercase()
+            || name == normalize(self.city)
+            || name == normalize(self.nickname)
+            || name == normalize(&full)
+            || self.aliases.contains(&name)
+    }
+
+    fn active_in(&self, year: i32) -> bool {
+        year >= self.from && self.to.is_none_or(|to| year <= to)
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Maps a user supplied team reference ("Lakers", "Sixers", "Seattle", "LAL") to the
+// abbreviations stored in player_box_scores. When a season is given, franchises that
+// were not playing under that name in that season are dropped, unless that leaves nothing.
+pub fn resolve_team(name: &str, season: Option<&str>) -> Option<Vec<&'static str>> {
+    let original = name.trim();
+    let name = normalize(name);
+    if UPPERCASE_ONLY.contains(&name.as_str()) && original != original.to_uppercase() {
+        return None;
+    }
+    let matches: Vec<&TeamName> = TEAMS.iter().filter(|t| t.matches(&name)).collect();
+    if matches.is_empty() {
+        return None;
+    }
+
+    let in_season: Vec<&TeamName> = match season.and_then(season_start_year) {
+        Some(year) => matches.iter().copied().filter(|t| t.active_in(year)).collect(),
+        None => Vec::new(),
+    };
+    let selected = if in_season.is_empty() { matches } else { in_season };
+
+    let mut abbreviations: Vec<&'static str> = Vec::new();
+    for team in selected {
+        if !abbreviations.contains(&team.abbreviation) {
+            abbreviations.push(team.abbreviation);
+        }
+    }
+    Some(abbreviations)
+}
+
+fn season_start_year(season: &str) -> Option<i32> {
+    season.get(..4)?.parse().ok()
+}
+
+// SQL list of abbreviations for a team filter, falling back to the raw value as given
+pub fn team_sql_list(name: &str, season: Option<&str>) -> String {
+    match resolve_team(name, season) {
+        Some(abbreviations) => abbreviations
+            .iter()
+            .map(|a| format!("'{}'", a))
+            .collect::<Vec<_>>()
+            .join(", "),
+        None => format!("'{}'", name.replace("'", "''")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_names_nicknames_cities_and_aliases() {
+        assert_eq!(resolve_team("LAL", None), Some(vec!["LAL"]));
+        assert_eq!(resolve_team("Celtics", None), Some(vec!["BOS"]));
+        assert_eq!(resolve_team("golden state warriors", None), Some(vec!["GSW"]));
+        assert_eq!(resolve_team("Sixers", None), Some(vec!["PHI"]));
+        assert_eq!(resolve_team("Boston", None), Some(vec!["BOS"]));
+        assert_eq!(resolve_team("Gotham", None), None);
+    }
+
+    #[test]
+    fn short_forms_that_are_words_need_capitals() {
+        for word in ["no", "No", "sa", "ny", "gs", "cs", "was", "min"] {
+            assert_eq!(resolve_team(word, None), None, "{}", word);
+        }
+        assert_eq!(resolve_team("NO", None), Some(vec!["NOP"]));
+        assert_eq!(resolve_team("NY", None), Some(vec!["NYK"]));
+        assert_eq!(resolve_team("SA", None), Some(vec!["SAS"]));
+        assert_eq!(resolve_team("WAS", None), Some(vec!["WAS"]));
+        assert_eq!(resolve_team("MIN", None), Some(vec!["MIN"]));
+    }
+
+    #[test]
+    fn season_picks_the_franchise_playing_under_that_name() {
+        assert_eq!(resolve_team("Hornets", Some("1995-96")), Some(vec!["CHH"]));
+        assert_eq!(resolve_team("Hornets", Some("2010-11")), Some(vec!["NOH"]));
+        assert_eq!(resolve_team("Hornets", Some("2020-21")), Some(vec!["CHA"]));
+        assert_eq!(resolve_team("Seattle", Some("2020-21")), Some(vec!["SEA"]));
+    }
+}
@@ -0,0 +1,193 @@
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio_postgres::Client;
+
+use crate::llm::{LlmResponse, LlmUsage};
+use super::query::AppState;
+
+const DEFAULT_DAYS: i32 = 30;
+
+pub async fn create_usage_table(client: &Client) -> Result<(), String> {
+    client
+        .batch_execute(
+            "CREATE TABLE IF NOT EXISTS llm_usage (
+                id BIGSERIAL PRIMARY KEY,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                endpoint VARCHAR(50) NOT NULL,
+                api_key VARCHAR(50) NOT NULL,
+                provider VARCHAR(50) NOT NULL,
+                model VARCHAR(100) NOT NULL,
+                input_tokens BIGINT NOT NULL,
+                output_tokens BIGINT NOT NULL,
+                latency_ms BIGINT NOT NULL,
+                cost_usd DOUBLE PRECISION
+            );
+            CREATE INDEX IF NOT EXISTS llm_usage_created_at_idx ON llm_usage (created_at)",
+        )
+        .await
+        .map_err(|e| format!("Failed to create llm_usage: {}", e))
+}
+
+// Callers are told apart by their X-API-Key header. Only a prefix is stored so the
+// usage table never holds a usable key.
+pub fn api_key_label(headers: &HeaderMap) -> String {
+    headers
+        .get("x-api-key")
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .map(|key| key.chars().take(8).collect())
+        .unwrap_or_else(|| "anonymous".to_string())
+}
+
+// Records the usage of a response and, in hedged mode, of the request that lost the race
+// once it finishes
+pub fn record_response_usage(client: Arc<Client>, endpoint: &str, api_key: String, response: &mut LlmResponse) {
+    record_usage(client.clone(), endpoint, api_key.clone(), response.usage.clone());
+    if let Some(pending) = response.hedge_usage.take() {
+        let endpoint = endpoint.to_string();
+        tokio::spawn(async move {
+            if let Ok(usage) = pending.await {
+                record_usage(client, &endpoint, api_key, usage);
+            }
+        });
+    }
+}
+
+// Writes happen in the background so a slow or missing usage table never delays a response
+pub fn record_usage(client: Arc<Client>, endpoint: &str, api_key: String, usage: LlmUsage) {
+    let endpoint = endpoint.to_string();
+    tokio::spawn(async move {
+        let result = client
+            .execute(
+                "INSERT INTO llm_usage (endpoint, api_key, provider, model, input_tokens, output_tokens, latency_ms, cost_usd)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                &[
+                    &endpoint,
+                    &api_key,
+                    &usage.provider,
+                    &usage.model,
+                    &(usage.input_tokens as i64),
+                    &(usage.output_tokens as i64),
+                    &(usage.latency_ms as i64),
+                    &usage.cost_usd,
+                ],
+            )
+            .await;
+        if let Err(e) = result {
+            eprintln!("Failed to record LLM usage: {}", e);
+        }
+    });
+}
+
+#[derive(Deserialize)]
+pub struct UsageParams {
+    pub days: Option<i32>,
+}
+
+#[derive(Serialize)]
+pub struct UsageBucket {
+    pub key: String,
+    pub calls: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub avg_latency_ms: f64,
+    pub cost_usd: f64,
+}
+
+#[derive(Serialize)]
+pub struct UsageReport {
+    pub days: i32,
+    pub total: UsageBucket,
+    pub by_day: Vec<UsageBucket>,
+    pub by_api_key: Vec<UsageBucket>,
+    pub by_model: Vec<UsageBucket>,
+}
+
+// Admin endpoints need ADMIN_TOKEN to be set and sent as a bearer token
+pub fn check_admin(headers: &HeaderMap) -> Result<(), (StatusCode, String)> {
+    let expected = std::env::var("ADMIN_TOKEN")
+        .ok()
+        .filter(|t| !t.is_empty())
+        .ok_or((StatusCode::FORBIDDEN, "Admin endpoints are disabled, set ADMIN_TOKEN".to_string()))?;
+
+    let provided = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    if provided != Some(expected.as_str()) {
+        return Err((StatusCode::UNAUTHORIZED, "Invalid admin token".to_string()));
+    }
+    Ok(())
+}
+
+async fn usage_buckets(client: &Client, key_sql: &str, days: i32) -> Result<Vec<UsageBucket>, String> {
+    let query = format!(
+        "SELECT {key_sql} AS key, COUNT(*), COALESCE(SUM(input_tokens), 0)::int8, COALESCE(SUM(output_tokens), 0)::int8,
+                COALESCE(AVG(latency_ms), 0)::float8, COALESCE(SUM(cost_usd), 0)::float8
+         FROM llm_usage
+         WHERE created_at >= now() - make_interval(days => {days})
+         GROUP BY 1
+         ORDER BY 1"
+    );
+
+    let rows = client
+        .query(&query, &[])
+        .await
+        .map_err(|e| format!("Usage query error: {}", e))?;
+
+    Ok(rows
+        .iter()
+        .map(|row| UsageBucket {
+            key: row.get(0),
+            calls: row.get(1),
+            input_tokens: row.get(2),
+            output_tokens: row.get(3),
+            avg_latency_ms: row.get(4),
+            cost_usd: row.get(5),
+        })
+        .collect())
+}
+
+pub async fn get_llm_usage(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(params): Query<UsageParams>,
+) -> Result<Json<UsageReport>, (StatusCode, String)> {
+    check_admin(&headers)?;
+
+    let days = params.days.unwrap_or(DEFAULT_DAYS).clamp(1, 366);
+    let client = &state.db_client;
+    let internal = |e: String| (StatusCode::INTERNAL_SERVER_ERROR, e);
+
+    let total = usage_buckets(client, "'total'::text", days)
+        .await
+        .map_err(internal)?
+        .pop()
+        .unwrap_or(UsageBucket {
+            key: "total".to_string(),
+            calls: 0,
+            input_tokens: 0,
+            output_tokens: 0,
+            avg_latency_ms: 0.0,
+            cost_usd: 0.0,
+        });
+
+    Ok(Json(UsageReport {
+        days,
+        total,
+        by_day: usage_buckets(client, "to_char(created_at, 'YYYY-MM-DD')", days)
+            .await
+            .map_err(internal)?,
+        by_api_key: usage_buckets(client, "api_key::text", days).await.map_err(internal)?,
+        by_model: usage_buckets(client, "(provider || '/' || model)", days)
+            .await
+            .map_err(internal)?,
+    }))
+}
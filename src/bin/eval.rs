@@ -0,0 +1,454 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio_postgres::{Client, NoTls};
+
+use player_stats_backend::api::boxscores::models::QueryParams;
+use player_stats_backend::api::boxscores::schema::llm_query_schema;
+use player_stats_backend::api::query::query_system_prompt;
+use player_stats_backend::api::catalog::SchemaCatalog;
+use player_stats_backend::api::guard::delimit;
+use player_stats_backend::api::examples::{render_examples, Example, ExampleMode, ExampleStore, EXAMPLE_COUNT};
+use player_stats_backend::api::sql::{execute_sql_query, sql_system_prompt};
+use player_stats_backend::api::teams::resolve_team;
+use player_stats_backend::llm::{get_provider, PromptRef, ProviderChain, PromptRegistry, PromptTemplate, LlmUsage, QUERY_PROMPT_ID, SQL_PROMPT_ID};
+
+const USAGE: &str = "usage: eval [questions.json] [--out <dir>] [--compare <report.json>] [--mode query|sql] [--query-prompt <version>] [--sql-prompt <version>]";
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum EvalMode {
+    Query,
+    Sql,
+}
+
+#[derive(Deserialize)]
+struct Question {
+    id: String,
+    mode: EvalMode,
+    question: String,
+    expected: Option<Value>,
+    expected_sql: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct QuestionResult {
+    id: String,
+    mode: EvalMode,
+    question: String,
+    exact: bool,
+    score: f64,
+    output: Option<String>,
+    error: Option<String>,
+    latency_ms: u128,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    usage: Option<LlmUsage>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Summary {
+    questions: usize,
+    errors: usize,
+    exact_matches: usize,
+    exact_rate: f64,
+    mean_score: f64,
+    query_mean_score: f64,
+    sql_mean_overlap: f64,
+    #[serde(default)]
+    total_cost_usd: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Report {
+    provider: String,
+    model: String,
+    query_prompt: PromptRef,
+    sql_prompt: PromptRef,
+    started_at: u64,
+    questions_file: String,
+    summary: Summary,
+    results: Vec<QuestionResult>,
+}
+
+struct Args {
+    questions: String,
+    out_dir: String,
+    compare: Option<String>,
+    mode: Option<EvalMode>,
+    query_prompt: Option<String>,
+    sql_prompt: Option<String>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        questions: "eval/questions.json".to_string(),
+        out_dir: "eval/reports".to_string(),
+        compare: None,
+        mode: None,
+        query_prompt: None,
+        sql_prompt: None,
+    };
+
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--out" => args.out_dir = iter.next().ok_or(USAGE)?,
+            "--compare" => args.compare = Some(iter.next().ok_or(USAGE)?),
+            "--query-prompt" => args.query_prompt = Some(iter.next().ok_or(USAGE)?),
+            "--sql-prompt" => args.sql_prompt = Some(iter.next().ok_or(USAGE)?),
+            "--mode" => {
+                args.mode = Some(match iter.next().as_deref() {
+                    Some("query") => EvalMode::Query,
+                    Some("sql") => EvalMode::Sql,
+                    _ => return Err(USAGE.to_string()),
+                })
+            }
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ => args.questions = arg,
+        }
+    }
+
+    Ok(args)
+}
+
+// QueryParams as a flat object of the filters that are actually set
+fn canonical_params(value: &Value) -> Result<serde_json::Map<String, Value>, String> {
+    let mut params: QueryParams = serde_json::from_value(value.clone()).map_err(|e| e.to_string())?;
+    params.normalize()?;
+
+    let Value::Object(map) = serde_json::to_value(&params).map_err(|e| e.to_string())? else {
+        return Err("QueryParams did not serialize to an object".to_string());
+    };
+    Ok(map.into_iter().filter(|(_, v)| !v.is_null()).collect())
+}
+
+fn values_match(key: &str, expected: &Value, actual: &Value) -> bool {
+    match (key, expected, actual) {
+        ("team" | "opponent", Value::String(e), Value::String(a)) => {
+            match (resolve_team(e, None), resolve_team(a, None)) {
+                (Some(mut e), Some(mut a)) => {
+                    e.sort();
+                    a.sort();
+                    e == a
+                }
+                _ => e.eq_ignore_ascii_case(a),
+            }
+        }
+        (_, Value::String(e), Value::String(a)) => e.eq_ignore_ascii_case(a),
+        (_, Value::Number(e), Value::Number(a)) => e.as_f64() == a.as_f64(),
+        _ => expected == actual,
+    }
+}
+
+// Exact when every expected filter matches and nothing extra was set; otherwise the F1 of matched filters
+fn score_params(expected: &Value, actual: &Value) -> Result<(bool, f64), String> {
+    let expected = canonical_params(expected)?;
+    let actual = canonical_params(actual)?;
+
+    if expected.is_empty() && actual.is_empty() {
+        return Ok((true, 1.0));
+    }
+
+    let matched = expected
+        .iter()
+        .filter(|(k, e)| actual.get(*k).is_some_and(|a| values_match(k, e, a)))
+        .count();
+
+    let exact = matched == expected.len() && matched == actual.len();
+    if matched == 0 {
+        return Ok((exact, 0.0));
+    }
+
+    let precision = matched as f64 / actual.len() as f64;
+    let recall = matched as f64 / expected.len() as f64;
+    Ok((exact, 2.0 * precision * recall / (precision + recall)))
+}
+
+// Multiset Jaccard overlap of the two result sets, compared on the expected columns
+async fn score_sql(client: &Client, expected_sql: &str, actual_sql: &str) -> Result<(bool, f64), String> {
+    let expected = execute_sql_query(client, expected_sql).await?;
+    let actual = execute_sql_query(client, actual_sql).await?;
+
+    let columns: Vec<String> = expected
+        .first()
+        .and_then(Value::as_object)
+        .map(|row| row.keys().cloned().collect())
+        .unwrap_or_default();
+
+    let project = |rows: &[Value]| -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for row in rows {
+            let key: Vec<&Value> = columns
+                .iter()
+                .map(|c| row.get(c).unwrap_or(&Value::Null))
+                .collect();
+            *counts.entry(serde_json::to_string(&key).unwrap_or_default()).or_insert(0) += 1;
+        }
+        counts
+    };
+
+    let (expected, actual) = (project(&expected), project(&actual));
+    let intersection: usize = expected
+        .iter()
+        .map(|(k, n)| (*n).min(*actual.get(k).unwrap_or(&0)))
+        .sum();
+    let union: usize = expected.values().sum::<usize>() + actual.values().sum::<usize>() - intersection;
+
+    if union == 0 {
+        return Ok((true, 1.0));
+    }
+    Ok((intersection == union, intersection as f64 / union as f64))
+}
+
+struct Prompts<'a> {
+    query: &'a PromptTemplate,
+    sql: &'a PromptTemplate,
+    schema: String,
+}
+
+// Few-shot examples for a question, leaving out the question itself since the eval set
+// is also one of the example seeds
+async fn examples_for(store: &ExampleStore, mode: ExampleMode, question: &str) -> String {
+    let examples: Vec<Example> = store
+        .similar(mode, question, EXAMPLE_COUNT + 1)
+        .await
+        .into_iter()
+        .filter(|e| e.question != question)
+        .take(EXAMPLE_COUNT)
+        .collect();
+    render_examples(&examples)
+}
+
+async fn run_question(llm_provider: &ProviderChain, prompts: &Prompts<'_>, examples: &ExampleStore, db: Option<&Client>, question: &Question) -> QuestionResult {
+    let started = Instant::now();
+    let mut result = QuestionResult {
+        id: question.id.clone(),
+        mode: question.mode,
+        question: question.question.clone(),
+        exact: false,
+        score: 0.0,
+        output: None,
+        error: None,
+        latency_ms: 0,
+        usage: None,
+    };
+
+    let outcome: Result<(bool, f64), String> = async {
+        match question.mode {
+            EvalMode::Query => {
+                let expected = question.expected.as_ref().ok_or("query question without \"expected\"")?;
+                let response = llm_provider
+                    .prompt_with_schema(
+                        &query_system_prompt(prompts.query, &examples_for(examples, ExampleMode::Query, &question.question).await),
+                        &delimit(&question.question),
+                        llm_query_schema(),
+                    )
+                    .await
+                    .map_err(|e| e.to_string())?;
+                result.usage = Some(response.usage);
+                result.output = Some(response.text.trim().to_string());
+                let actual: Value = serde_json::from_str(response.text.trim()).map_err(|e| format!("Failed to parse JSON: {}", e))?;
+                score_params(expected, &actual)
+            }
+            EvalMode::Sql => {
+                let expected_sql = question.expected_sql.as_deref().ok_or("sql question without \"expected_sql\"")?;
+                let db = db.ok_or("no fixture database, set EVAL_DATABASE_URL")?;
+                let response = llm_provider
+                    .prompt(
+                        &sql_system_prompt(prompts.sql, &prompts.schema, &examples_for(examples, ExampleMode::Sql, &question.question).await),
+                        &delimit(&question.question),
+                    )
+                    .await
+                    .map_err(|e| e.to_string())?;
+                result.usage = Some(response.usage);
+                let sql = response.text.trim().to_string();
+                result.output = Some(sql.clone());
+                score_sql(db, expected_sql, &sql).await
+            }
+        }
+    }
+    .await;
+
+    result.latency_ms = started.elapsed().as_millis();
+    match outcome {
+        Ok((exact, score)) => {
+            result.exact = exact;
+            result.score = score;
+        }
+        Err(e) => result.error = Some(e),
+    }
+    result
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> f64 {
+    let (sum, count) = values.fold((0.0, 0usize), |(s, c), v| (s + v, c + 1));
+    if count == 0 { 0.0 } else { sum / count as f64 }
+}
+
+fn summarize(results: &[QuestionResult]) -> Summary {
+    let exact_matches = results.iter().filter(|r| r.exact).count();
+    Summary {
+        questions: results.len(),
+        errors: results.iter().filter(|r| r.error.is_some()).count(),
+        exact_matches,
+        exact_rate: if results.is_empty() { 0.0 } else { exact_matches as f64 / results.len() as f64 },
+        mean_score: mean(results.iter().map(|r| r.score)),
+        query_mean_score: mean(results.iter().filter(|r| r.mode == EvalMode::Query).map(|r| r.score)),
+        sql_mean_overlap: mean(results.iter().filter(|r| r.mode == EvalMode::Sql).map(|r| r.score)),
+        total_cost_usd: results
+            .iter()
+            .filter_map(|r| r.usage.as_ref().and_then(|u| u.cost_usd))
+            .sum(),
+    }
+}
+
+fn print_comparison(previous: &Report, current: &Report) {
+    let rows = [
+        ("exact_rate", previous.summary.exact_rate, current.summary.exact_rate),
+        ("mean_score", previous.summary.mean_score, current.summary.mean_score),
+        ("query_mean_score", previous.summary.query_mean_score, current.summary.query_mean_score),
+        ("sql_mean_overlap", previous.summary.sql_mean_overlap, current.summary.sql_mean_overlap),
+    ];
+
+    println!(
+        "\ncompared to {} / {} with query@{} sql@{} (started {}):",
+        previous.provider, previous.model, previous.query_prompt.version, previous.sql_prompt.version, previous.started_at
+    );
+    for (name, before, after) in rows {
+        println!("  {:<18} {:.3} -> {:.3} ({:+.3})", name, before, after, after - before);
+    }
+
+    let before: HashMap<&str, &QuestionResult> = previous.results.iter().map(|r| (r.id.as_str(), r)).collect();
+    for result in &current.results {
+        if let Some(prev) = before.get(result.id.as_str())
+            && (prev.score - result.score).abs() > f64::EPSILON
+        {
+            println!("  {:<32} {:.3} -> {:.3}", result.id, prev.score, result.score);
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let llm_provider = get_provider().expect("an LLM provider API key must be set to run the eval");
+
+    let registry = PromptRegistry::load(&std::env::var("PROMPTS_DIR").unwrap_or_else(|_| "prompts".to_string()));
+    let mut prompts = match (
+        registry.select(QUERY_PROMPT_ID, args.query_prompt.as_deref(), ""),
+        registry.select(SQL_PROMPT_ID, args.sql_prompt.as_deref(), ""),
+    ) {
+        (Ok(query), Ok(sql)) => Prompts {
+            query,
+            sql,
+            schema: String::new(),
+        },
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let questions: Vec<Question> = serde_json::from_str(
+        &std::fs::read_to_string(&args.questions).expect("Failed to read questions file"),
+    )
+    .expect("Failed to parse questions file");
+
+    let questions: Vec<Question> = questions
+        .into_iter()
+        .filter(|q| args.mode.is_none_or(|m| m == q.mode))
+        .collect();
+
+    let db = match std::env::var("EVAL_DATABASE_URL").or_else(|_| std::env::var("DATABASE_URL_READONLY")) {
+        Ok(url) => match tokio_postgres::connect(&url, NoTls).await {
+            Ok((client, connection)) => {
+                tokio::spawn(async move {
+                    if let Err(e) = connection.await {
+                        eprintln!("Fixture database connection error: {}", e);
+                    }
+                });
+                Some(client)
+            }
+            Err(e) => {
+                eprintln!("Failed to connect to fixture database, SQL questions will error: {}", e);
+                None
+            }
+        },
+        Err(_) => None,
+    };
+
+    let schema_catalog = SchemaCatalog::default();
+    if let Some(ref client) = db
+        && let Err(e) = schema_catalog.refresh(client).await
+    {
+        eprintln!("{}, the SQL prompt will use the built-in column list", e);
+    }
+    prompts.schema = schema_catalog.prompt_text().await;
+
+    let examples = ExampleStore::default();
+
+    let started_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let mut results = Vec::new();
+    for question in &questions {
+        let result = run_question(&llm_provider, &prompts, &examples, db.as_ref(), question).await;
+        println!(
+            "{:<32} {:<5} score {:.3}{}{}",
+            result.id,
+            if result.exact { "exact" } else { "" },
+            result.score,
+            if result.error.is_some() { " error: " } else { "" },
+            result.error.as_deref().unwrap_or("")
+        );
+        results.push(result);
+    }
+
+    let report = Report {
+        provider: llm_provider.name(),
+        model: llm_provider.model(),
+        query_prompt: prompts.query.reference(),
+        sql_prompt: prompts.sql.reference(),
+        started_at,
+        questions_file: args.questions.clone(),
+        summary: summarize(&results),
+        results,
+    };
+
+    println!(
+        "\n{} questions, {} exact ({:.1}%), mean score {:.3}, query {:.3}, sql overlap {:.3}, {} errors",
+        report.summary.questions,
+        report.summary.exact_matches,
+        report.summary.exact_rate * 100.0,
+        report.summary.mean_score,
+        report.summary.query_mean_score,
+        report.summary.sql_mean_overlap,
+        report.summary.errors
+    );
+
+    std::fs::create_dir_all(&args.out_dir).expect("Failed to create report directory");
+    let path = format!("{}/eval-{}.json", args.out_dir, started_at);
+    std::fs::write(&path, serde_json::to_string_pretty(&report).expect("Failed to serialize report"))
+        .expect("Failed to write report");
+    println!("report written to {}", path);
+
+    if let Some(compare) = args.compare {
+        match std::fs::read_to_string(&compare)
+            .map_err(|e| e.to_string())
+            .and_then(|s| serde_json::from_str::<Report>(&s).map_err(|e| e.to_string()))
+        {
+            Ok(previous) => print_comparison(&previous, &report),
+            Err(e) => eprintln!("Failed to read report to compare against: {}", e),
+        }
+    }
+}
@@ -0,0 +1,453 @@
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+use super::provider::LLMProvider;
+use super::usage::{LlmResponse, LlmUsage};
+
+type LlmError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Clone, Copy)]
+pub struct BreakerConfig {
+    pub failure_threshold: u32,
+    pub cooldown: Duration,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+// Stops sending requests to a provider after repeated failures. Once the cooldown has
+// passed requests go through again, but the failure count is kept: a success closes the
+// breaker, while the first failure reopens it at once.
+struct CircuitBreaker {
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    fn is_open(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.open_until.is_some_and(|until| Instant::now() < until)
+    }
+
+    fn record_success(&self) {
+        *self.state.lock().unwrap() = BreakerState::default();
+    }
+
+    fn record_failure(&self, config: &BreakerConfig, rate_limited: bool) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures += 1;
+        if rate_limited || state.consecutive_failures >= config.failure_threshold {
+            state.open_until = Some(Instant::now() + config.cooldown);
+            return true;
+        }
+        false
+    }
+}
+
+pub struct ChainMember {
+    pub provider: LLMProvider,
+    pub timeout: Duration,
+    breaker: CircuitBreaker,
+}
+
+impl ChainMember {
+    pub fn new(provider: LLMProvider, timeout: Duration) -> Self {
+        ChainMember {
+            provider,
+            timeout,
+            breaker: CircuitBreaker {
+                state: Mutex::new(BreakerState::default()),
+            },
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Request {
+    Text,
+    Schema(Value),
+}
+
+// Ordered list of providers. Each request goes to the first provider whose circuit is
+// closed and fails over to the next one on errors, timeouts, rate limits or (for
+// structured output) invalid JSON. In hedged mode the first two providers are raced and
+// the first valid response wins; the usage of the other one is reported once it finishes.
+pub struct ProviderChain {
+    members: Vec<Arc<ChainMember>>,
+    hedged: bool,
+    breaker: BreakerConfig,
+}
+
+impl ProviderChain {
+    pub fn new(members: Vec<ChainMember>, hedged: bool, breaker: BreakerConfig) -> Option<Self> {
+        if members.is_empty() {
+            return None;
+        }
+        Some(ProviderChain {
+            members: members.into_iter().map(Arc::new).collect(),
+            hedged,
+            breaker,
+        })
+    }
+
+    pub fn name(&self) -> String {
+        self.members
+            .iter()
+            .map(|m| m.provider.name())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    pub fn model(&self) -> String {
+        self.members
+            .iter()
+            .map(|m| m.provider.model())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    pub async fn prompt(&self, system_prompt: &str, user_query: &str) -> Result<LlmResponse, LlmError> {
+        self.run(system_prompt, user_query, Request::Text).await
+    }
+
+    pub async fn prompt_with_schema(
+        &self,
+        system_prompt: &str,
+        user_query: &str,
+        schema: Value,
+    ) -> Result<LlmResponse, LlmError> {
+        self.run(system_prompt, user_query, Request::Schema(schema)).await
+    }
+
+    async fn run(&self, system_prompt: &str, user_query: &str, request: Request) -> Result<LlmResponse, LlmError> {
+        // With every circuit open, trying anyway beats failing without a request
+        let mut candidates: Vec<&Arc<ChainMember>> = self.members.iter().filter(|m| !m.breaker.is_open()).collect();
+        if candidates.is_empty() {
+            candidates = self.members.iter().collect();
+        }
+
+        let mut errors: Vec<String> = Vec::new();
+
+        if self.hedged && candidates.len() >= 2 {
+            let (first, second) = (candidates[0], candidates[1]);
+            candidates.drain(..2);
+
+            // Spawned so the slower request can finish and report its usage after the race
+            let mut a = self.spawn_call(first, system_prompt, user_query, &request);
+            let mut b = self.spawn_call(second, system_prompt, user_query, &request);
+
+            let (winner, other) = tokio::select! {
+                result = &mut a => (result, Either::B),
+                result = &mut b => (result, Either::A),
+            };
+            let other = match other {
+                Either::A => a,
+                Either::B => b,
+            };
+            match joined(winner) {
+                Ok(mut response) => {
+                    response.hedge_usage = Some(forward_usage(other));
+                    return Ok(response);
+                }
+                Err(e) => errors.push(e),
+            }
+            match joined(other.await) {
+                Ok(response) => return Ok(response),
+                Err(e) => errors.push(e),
+            }
+        }
+
+        for member in candidates {
+            match call(member, &self.breaker, system_prompt, user_query, &request).await {
+                Ok(response) => return Ok(response),
+                Err(e) => errors.push(e),
+            }
+        }
+
+        Err(format!("All LLM providers failed: {}", errors.join("; ")).into())
+    }
+
+    fn spawn_call(
+        &self,
+        member: &Arc<ChainMember>,
+        system_prompt: &str,
+        user_query: &str,
+        request: &Request,
+    ) -> JoinHandle<Result<LlmResponse, String>> {
+        let member = member.clone();
+        let breaker = self.breaker;
+        let system_prompt = system_prompt.to_string();
+        let user_query = user_query.to_string();
+        let request = request.clone();
+        tokio::spawn(async move { call(&member, &breaker, &system_prompt, &user_query, &request).await })
+    }
+}
+
+async fn call(
+    member: &ChainMember,
+    breaker: &BreakerConfig,
+    system_prompt: &str,
+    user_query: &str,
+    request: &Request,
+) -> Result<LlmResponse, String> {
+    let name = member.provider.name();
+    let call = async {
+        match request {
+            Request::Text => member.provider.prompt(system_prompt, user_query).await,
+            Request::Schema(schema) => {
+                member
+                    .provider
+                    .prompt_with_schema(system_prompt, user_query, schema.clone())
+                    .await
+            }
+        }
+    };
+
+    let result = match timeout(member.timeout, call).await {
+        Err(_) => Err(format!("timed out after {}ms", member.timeout.as_millis())),
+        Ok(Err(e)) => Err(e.to_string()),
+        Ok(Ok(response)) => match request {
+            Request::Schema(_) if !is_json_object(&response.text) => {
+                Err(format!("returned invalid structured output: {}", response.text))
+            }
+            _ => Ok(response),
+        },
+    };
+
+    match result {
+        Ok(response) => {
+            member.breaker.record_success();
+            Ok(response)
+        }
+        Err(e) => {
+            let lowered = e.to_lowercase();
+            let rate_limited = lowered.contains("429") || lowered.contains("rate limit");
+            if member.breaker.record_failure(breaker, rate_limited) {
+                eprintln!(
+                    "LLM provider {} circuit opened for {}s",
+                    name,
+                    breaker.cooldown.as_secs()
+                );
+            }
+            eprintln!("LLM provider {} failed: {}", name, e);
+            Err(format!("{}: {}", name, e))
+        }
+    }
+}
+
+enum Either {
+    A,
+    B,
+}
+
+fn joined(result: Result<Result<LlmResponse, String>, tokio::task::JoinError>) -> Result<LlmResponse, String> {
+    result.unwrap_or_else(|e| Err(format!("provider task failed: {}", e)))
+}
+
+// Sends the usage of a hedged request that lost the race once it completes
+fn forward_usage(pending: JoinHandle<Result<LlmResponse, String>>) -> oneshot::Receiver<LlmUsage> {
+    let (sender, receiver) = oneshot::channel();
+    tokio::spawn(async move {
+        if let Ok(response) = joined(pending.await) {
+            let _ = sender.send(response.usage);
+        }
+    });
+    receiver
+}
+
+fn is_json_object(text: &str) -> bool {
+    serde_json::from_str::<Value>(text.trim()).is_ok_and(|v| v.is_object())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::provider::StubProvider;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const TIMEOUT: Duration = Duration::from_millis(200);
+    const NEVER: usize = usize::MAX;
+
+    fn stub(name: &'static str, text: &str, delay_ms: u64, failures: usize, error: &'static str) -> (ChainMember, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let provider = LLMProvider::Stub(StubProvider {
+            name,
+            text: text.to_string(),
+            delay: Duration::from_millis(delay_ms),
+            failures,
+            error,
+            calls: calls.clone(),
+        });
+        (ChainMember::new(provider, TIMEOUT), calls)
+    }
+
+    fn answers(name: &'static str, delay_ms: u64) -> (ChainMember, Arc<AtomicUsize>) {
+        stub(name, "{\"player\": \"Curry\"}", delay_ms, 0, "")
+    }
+
+    fn fails(name: &'static str, failures: usize) -> (ChainMember, Arc<AtomicUsize>) {
+        stub(name, "{}", 0, failures, "connection refused")
+    }
+
+    fn hangs(name: &'static str) -> (ChainMember, Arc<AtomicUsize>) {
+        stub(name, "{}", 3_600_000, 0, "")
+    }
+
+    fn chain(members: Vec<ChainMember>, hedged: bool, failure_threshold: u32, cooldown_ms: u64) -> ProviderChain {
+        let breaker = BreakerConfig {
+            failure_threshold,
+            cooldown: Duration::from_millis(cooldown_ms),
+        };
+        ProviderChain::new(members, hedged, breaker).unwrap()
+    }
+
+    fn calls(counter: &Arc<AtomicUsize>) -> usize {
+        counter.load(Ordering::SeqCst)
+    }
+
+    async fn answered_by(chain: &ProviderChain) -> Result<String, String> {
+        chain
+            .prompt("system", "question")
+            .await
+            .map(|response| response.usage.provider)
+            .map_err(|e| e.to_string())
+    }
+
+    #[tokio::test]
+    async fn fails_over_in_order() {
+        let (a, a_calls) = fails("a", NEVER);
+        let (b, b_calls) = answers("b", 0);
+        let (c, c_calls) = answers("c", 0);
+        let chain = chain(vec![a, b, c], false, 5, 60_000);
+
+        assert_eq!(answered_by(&chain).await.unwrap(), "b");
+        assert_eq!((calls(&a_calls), calls(&b_calls), calls(&c_calls)), (1, 1, 0));
+    }
+
+    #[tokio::test]
+    async fn times_out_a_hanging_provider() {
+        let (a, _) = hangs("a");
+        let (b, _) = answers("b", 0);
+        let chain = chain(vec![a, b], false, 5, 60_000);
+
+        let started = Instant::now();
+        assert_eq!(answered_by(&chain).await.unwrap(), "b");
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn fails_over_on_invalid_structured_output() {
+        let (a, _) = stub("a", "not json", 0, 0, "");
+        let (b, _) = answers("b", 0);
+        let chain = chain(vec![a, b], false, 5, 60_000);
+
+        let response = chain.prompt_with_schema("system", "question", json!({})).await.unwrap();
+        assert_eq!(response.usage.provider, "b");
+        // Plain text requests take any text
+        assert_eq!(answered_by(&chain).await.unwrap(), "a");
+    }
+
+    #[tokio::test]
+    async fn reports_every_failure() {
+        let (a, _) = fails("a", NEVER);
+        let (b, _) = hangs("b");
+        let chain = chain(vec![a, b], false, 5, 60_000);
+
+        let error = answered_by(&chain).await.unwrap_err();
+        assert!(error.contains("a: connection refused"), "{}", error);
+        assert!(error.contains("b: timed out after 200ms"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn opens_and_recovers_the_circuit() {
+        let (a, a_calls) = fails("a", 3);
+        let (b, _) = answers("b", 0);
+        let chain = chain(vec![a, b], false, 2, 100);
+
+        // Two failures in a row open the circuit, after which a is skipped
+        assert_eq!(answered_by(&chain).await.unwrap(), "b");
+        assert_eq!(answered_by(&chain).await.unwrap(), "b");
+        assert_eq!(answered_by(&chain).await.unwrap(), "b");
+        assert_eq!(calls(&a_calls), 2);
+
+        // After the cooldown a is tried again, and one more failure reopens it at once
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(answered_by(&chain).await.unwrap(), "b");
+        assert_eq!(answered_by(&chain).await.unwrap(), "b");
+        assert_eq!(calls(&a_calls), 3);
+
+        // A success closes it again
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(answered_by(&chain).await.unwrap(), "a");
+        assert_eq!(answered_by(&chain).await.unwrap(), "a");
+        assert_eq!(calls(&a_calls), 5);
+    }
+
+    #[tokio::test]
+    async fn rate_limits_open_the_circuit_at_once() {
+        let (a, a_calls) = stub("a", "{}", 0, NEVER, "429 Too Many Requests");
+        let (b, _) = answers("b", 0);
+        let chain = chain(vec![a, b], false, 5, 60_000);
+
+        assert_eq!(answered_by(&chain).await.unwrap(), "b");
+        assert_eq!(answered_by(&chain).await.unwrap(), "b");
+        assert_eq!(calls(&a_calls), 1);
+    }
+
+    #[tokio::test]
+    async fn tries_open_circuits_when_all_are_open() {
+        let (a, a_calls) = fails("a", 1);
+        let chain = chain(vec![a], false, 1, 60_000);
+
+        assert!(answered_by(&chain).await.is_err());
+        assert_eq!(answered_by(&chain).await.unwrap(), "a");
+        assert_eq!(calls(&a_calls), 2);
+    }
+
+    #[tokio::test]
+    async fn hedging_takes_the_first_response_and_reports_both() {
+        let (a, a_calls) = answers("a", 150);
+        let (b, b_calls) = answers("b", 10);
+        let chain = chain(vec![a, b], true, 5, 60_000);
+
+        let started = Instant::now();
+        let response = chain.prompt("system", "question").await.unwrap();
+        assert_eq!(response.usage.provider, "b");
+        assert!(started.elapsed() < Duration::from_millis(150));
+        assert_eq!((calls(&a_calls), calls(&b_calls)), (1, 1));
+
+        let other = response.hedge_usage.unwrap().await.unwrap();
+        assert_eq!(other.provider, "a");
+        assert_eq!((other.input_tokens, other.output_tokens), (100, 10));
+    }
+
+    #[tokio::test]
+    async fn hedging_waits_for_the_other_request_after_a_failure() {
+        let (a, _) = fails("a", NEVER);
+        let (b, _) = answers("b", 50);
+        let (c, c_calls) = answers("c", 0);
+        let chain = chain(vec![a, b, c], true, 5, 60_000);
+
+        let response = chain.prompt("system", "question").await.unwrap();
+        assert_eq!(response.usage.provider, "b");
+        assert!(response.hedge_usage.is_none());
+        assert_eq!(calls(&c_calls), 0);
+    }
+
+    #[tokio::test]
+    async fn hedging_falls_through_to_the_rest_of_the_chain() {
+        let (a, _) = fails("a", NEVER);
+        let (b, _) = hangs("b");
+        let (c, _) = answers("c", 0);
+        let chain = chain(vec![a, b, c], true, 5, 60_000);
+
+        assert_eq!(answered_by(&chain).await.unwrap(), "c");
+    }
+}
@@ -1,3 +1,6 @@
+use std::time::Duration;
+
+use super::chain::{BreakerConfig, ChainMember, ProviderChain};
 use super::provider::LLMProvider;
 
 /// gemini
@@ -6,9 +9,76 @@ pub const GEMINI_MODEL: &str = "gemini-2.5-pro";
 /// openai
 pub const OPENAI_MODEL: &str = "gpt-5-mini";
 
-/// gemini() for Google Gemini
-/// openai() for OpenAI GPT
-pub fn get_provider() -> LLMProvider {
-    // LLMProvider::gemini()
-    LLMProvider::openai()
+/// anthropic (override with ANTHROPIC_MODEL)
+pub const ANTHROPIC_MODEL: &str = "claude-sonnet-4-5";
+pub const ANTHROPIC_MAX_TOKENS: u64 = 4096;
+
+/// ollama (override with OLLAMA_MODEL / OLLAMA_BASE_URL)
+pub const OLLAMA_MODEL: &str = "qwen2.5-coder:14b";
+pub const OLLAMA_BASE_URL: &str = "http://localhost:11434";
+
+const DEFAULT_PROVIDERS: &str = "openai";
+const DEFAULT_TIMEOUT_MS: u64 = 30_000;
+const DEFAULT_BREAKER_FAILURES: u32 = 3;
+const DEFAULT_BREAKER_COOLDOWN_SECS: u64 = 30;
+
+/// LLM_PROVIDERS: comma separated chain in failover order, e.g. "openai,gemini" (default: openai).
+/// Known providers: gemini, openai, anthropic, ollama, openai_compatible (needs
+/// OPENAI_COMPAT_BASE_URL and OPENAI_COMPAT_MODEL, optionally OPENAI_COMPAT_API_KEY)
+/// LLM_TIMEOUT_MS / LLM_TIMEOUT_<PROVIDER>_MS: per-call timeout (default: 30000)
+/// LLM_HEDGE: race the first two providers and take the first valid response
+/// LLM_BREAKER_FAILURES / LLM_BREAKER_COOLDOWN_SECS: circuit breaker settings
+/// Providers missing their API key or settings are skipped; None when none is left
+pub fn get_provider() -> Option<ProviderChain> {
+    let names = std::env::var("LLM_PROVIDERS").unwrap_or_else(|_| DEFAULT_PROVIDERS.to_string());
+    let default_timeout = env_number("LLM_TIMEOUT_MS").unwrap_or(DEFAULT_TIMEOUT_MS);
+
+    let mut members = Vec::new();
+    for name in names.split(',').map(|n| n.trim().to_lowercase()).filter(|n| !n.is_empty()) {
+        let provider = match name.as_str() {
+            "gemini" => LLMProvider::gemini(),
+            "openai" => LLMProvider::openai(),
+            "anthropic" => LLMProvider::anthropic(),
+            "ollama" => LLMProvider::ollama(),
+            "openai_compatible" => LLMProvider::openai_compatible(),
+            other => {
+                eprintln!("Unknown LLM provider '{}' in LLM_PROVIDERS", other);
+                continue;
+            }
+        };
+        let Some(provider) = provider else {
+            eprintln!("Skipping LLM provider '{}': API key or settings are missing", name);
+            continue;
+        };
+        let timeout = env_number(&format!("LLM_TIMEOUT_{}_MS", name.to_uppercase())).unwrap_or(default_timeout);
+        members.push(ChainMember::new(provider, Duration::from_millis(timeout)));
+    }
+
+    let hedged = matches!(std::env::var("LLM_HEDGE").as_deref(), Ok("1") | Ok("true") | Ok("yes"));
+    let breaker = BreakerConfig {
+        failure_threshold: env_number("LLM_BREAKER_FAILURES")
+            .map(|n| n as u32)
+            .unwrap_or(DEFAULT_BREAKER_FAILURES)
+            .max(1),
+        cooldown: Duration::from_secs(env_number("LLM_BREAKER_COOLDOWN_SECS").unwrap_or(DEFAULT_BREAKER_COOLDOWN_SECS)),
+    };
+
+    let chain = ProviderChain::new(members, hedged, breaker)?;
+    println!(
+        "LLM providers: {}{}",
+        chain.name(),
+        if hedged { " (hedged)" } else { "" }
+    );
+    Some(chain)
+}
+
+fn env_number(var: &str) -> Option<u64> {
+    let value = std::env::var(var).ok()?;
+    match value.trim().parse() {
+        Ok(n) => Some(n),
+        Err(_) => {
+            eprintln!("Ignoring {}: '{}' is not a number", var, value);
+            None
+        }
+    }
 }
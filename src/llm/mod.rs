@@ -1,7 +1,13 @@
+pub mod chain;
 pub mod config;
 pub mod prompts;
 pub mod provider;
+pub mod registry;
+pub mod usage;
 
+pub use chain::ProviderChain;
 pub use config::get_provider;
 pub use prompts::{QUERY_PROMPT, SQL_PROMPT};
 pub use provider::LLMProvider;
+pub use registry::{PromptRef, PromptRegistry, PromptTemplate, QUERY_PROMPT_ID, SQL_PROMPT_ID};
+pub use usage::{LlmResponse, LlmUsage};
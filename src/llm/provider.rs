@@ -1,44 +1,165 @@
-use rig::completion::Prompt;
+use rig::agent::{AgentBuilder, PromptResponse};
+use rig::completion::{AssistantContent, CompletionModel, Prompt, ToolDefinition, Usage};
+use rig::message::ToolChoice;
 use rig::prelude::*;
-use rig::providers::{gemini, openai};
-use serde_json::Value;
+use rig::providers::{anthropic, gemini, openai};
+use serde_json::{json, Value};
+use std::time::Instant;
 
-use super::config::{GEMINI_MODEL, OPENAI_MODEL};
+use super::config::{
+    ANTHROPIC_MAX_TOKENS, ANTHROPIC_MODEL, GEMINI_MODEL, OLLAMA_BASE_URL, OLLAMA_MODEL, OPENAI_MODEL,
+};
+use super::usage::{LlmResponse, LlmUsage};
+
+type LlmError = Box<dyn std::error::Error + Send + Sync>;
+
+// Name of the tool Anthropic is forced to call; its input is the structured response
+const STRUCTURED_OUTPUT_TOOL: &str = "respond";
 
 #[allow(dead_code)]
 pub enum LLMProvider {
     Gemini(gemini::Client),
     OpenAI(openai::Client),
+    Anthropic { client: anthropic::Client, model: String },
+    // Ollama is reached through its OpenAI-compatible /v1 API, which supports response_format
+    Ollama { client: openai::Client, model: String },
+    // Any server implementing the OpenAI Chat Completions API (vLLM, LM Studio, llama.cpp, ...)
+    OpenAICompatible { client: openai::Client, model: String },
+    #[cfg(test)]
+    Stub(StubProvider),
+}
+
+// Scripted provider for the chain tests: answers `text` after `delay`, except for the first
+// `failures` calls, which fail with `error`
+#[cfg(test)]
+pub struct StubProvider {
+    pub name: &'static str,
+    pub text: String,
+    pub delay: std::time::Duration,
+    pub failures: usize,
+    pub error: &'static str,
+    pub calls: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+}
+
+#[cfg(test)]
+impl StubProvider {
+    async fn respond(&self, started: Instant) -> Result<LlmResponse, LlmError> {
+        let call = self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        tokio::time::sleep(self.delay).await;
+        if call < self.failures {
+            return Err(self.error.into());
+        }
+        Ok(LlmResponse {
+            text: self.text.clone(),
+            usage: LlmUsage::new(self.name, "stub", 100, 10, started.elapsed().as_millis() as u64),
+            hedge_usage: None,
+        })
+    }
 }
 
 #[allow(dead_code)]
 impl LLMProvider {
-    pub fn gemini() -> Self {
-        let api_key = std::env::var("GEMINI_API_KEY")
-            .expect("GEMINI_API_KEY must be set");
-        LLMProvider::Gemini(gemini::Client::new(&api_key))
+    pub fn gemini() -> Option<Self> {
+        let api_key = std::env::var("GEMINI_API_KEY").ok()?;
+        Some(LLMProvider::Gemini(gemini::Client::new(&api_key)))
     }
 
-    pub fn openai() -> Self {
-        let api_key = std::env::var("OPENAI_API_KEY")
-            .expect("OPENAI_API_KEY must be set");
-        LLMProvider::OpenAI(openai::Client::new(&api_key))
+    pub fn openai() -> Option<Self> {
+        let api_key = std::env::var("OPENAI_API_KEY").ok()?;
+        Some(LLMProvider::OpenAI(openai::Client::new(&api_key)))
     }
 
-    pub async fn prompt(
-        &self,
-        system_prompt: &str,
-        user_query: &str,
-    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    pub fn anthropic() -> Option<Self> {
+        let api_key = std::env::var("ANTHROPIC_API_KEY").ok()?;
+        Some(LLMProvider::Anthropic {
+            client: anthropic::Client::new(&api_key),
+            model: env_or("ANTHROPIC_MODEL", ANTHROPIC_MODEL),
+        })
+    }
+
+    // Needs no key, the server is assumed to run locally unless OLLAMA_BASE_URL says otherwise
+    pub fn ollama() -> Option<Self> {
+        let base_url = env_or("OLLAMA_BASE_URL", OLLAMA_BASE_URL);
+        let client = openai::Client::builder("ollama")
+            .base_url(&format!("{}/v1", base_url.trim_end_matches('/')))
+            .build();
+        Some(LLMProvider::Ollama {
+            client,
+            model: env_or("OLLAMA_MODEL", OLLAMA_MODEL),
+        })
+    }
+
+    pub fn openai_compatible() -> Option<Self> {
+        let base_url = std::env::var("OPENAI_COMPAT_BASE_URL").ok()?;
+        let model = std::env::var("OPENAI_COMPAT_MODEL").ok()?;
+        let api_key = std::env::var("OPENAI_COMPAT_API_KEY").unwrap_or_else(|_| "none".to_string());
+        let client = openai::Client::builder(&api_key)
+            .base_url(base_url.trim_end_matches('/'))
+            .build();
+        Some(LLMProvider::OpenAICompatible { client, model })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LLMProvider::Gemini(_) => "gemini",
+            LLMProvider::OpenAI(_) => "openai",
+            LLMProvider::Anthropic { .. } => "anthropic",
+            LLMProvider::Ollama { .. } => "ollama",
+            LLMProvider::OpenAICompatible { .. } => "openai_compatible",
+            #[cfg(test)]
+            LLMProvider::Stub(stub) => stub.name,
+        }
+    }
+
+    pub fn model(&self) -> &str {
         match self {
+            LLMProvider::Gemini(_) => GEMINI_MODEL,
+            LLMProvider::OpenAI(_) => OPENAI_MODEL,
+            LLMProvider::Anthropic { model, .. }
+            | LLMProvider::Ollama { model, .. }
+            | LLMProvider::OpenAICompatible { model, .. } => model,
+            #[cfg(test)]
+            LLMProvider::Stub(_) => "stub",
+        }
+    }
+
+    fn usage(&self, usage: Usage, started: Instant) -> LlmUsage {
+        LlmUsage::new(
+            self.name(),
+            self.model(),
+            usage.input_tokens,
+            usage.output_tokens,
+            started.elapsed().as_millis() as u64,
+        )
+    }
+
+    fn response(&self, response: PromptResponse, started: Instant) -> LlmResponse {
+        LlmResponse {
+            usage: self.usage(response.total_usage, started),
+            text: response.output,
+            hedge_usage: None,
+        }
+    }
+
+    pub async fn prompt(&self, system_prompt: &str, user_query: &str) -> Result<LlmResponse, LlmError> {
+        let started = Instant::now();
+        let response = match self {
             LLMProvider::Gemini(client) => {
+                use rig::providers::gemini::completion::gemini_api_types::{
+                    AdditionalParameters, GenerationConfig,
+                };
+
+                // The Gemini client rejects requests without a generationConfig
+                let additional_params = AdditionalParameters::default()
+                    .with_config(GenerationConfig::default());
+
                 let agent = client
                     .agent(GEMINI_MODEL)
                     .preamble(system_prompt)
+                    .additional_params(serde_json::to_value(additional_params)?)
                     .build();
 
-                let response = agent.prompt(user_query).await?;
-                Ok(response)
+                agent.prompt(user_query).extended_details().await?
             }
             LLMProvider::OpenAI(client) => {
                 let agent = client
@@ -46,10 +167,28 @@ impl LLMProvider {
                     .preamble(system_prompt)
                     .build();
 
-                let response = agent.prompt(user_query).await?;
-                Ok(response)
+                agent.prompt(user_query).extended_details().await?
             }
-        }
+            LLMProvider::Anthropic { client, model } => {
+                let agent = client
+                    .agent(model)
+                    .preamble(system_prompt)
+                    .max_tokens(ANTHROPIC_MAX_TOKENS)
+                    .build();
+
+                agent.prompt(user_query).extended_details().await?
+            }
+            #[cfg(test)]
+            LLMProvider::Stub(stub) => return stub.respond(started).await,
+            LLMProvider::Ollama { client, model } | LLMProvider::OpenAICompatible { client, model } => {
+                let agent = AgentBuilder::new(client.completion_model(model).completions_api())
+                    .preamble(system_prompt)
+                    .build();
+
+                agent.prompt(user_query).extended_details().await?
+            }
+        };
+        Ok(self.response(response, started))
     }
 
     pub async fn prompt_with_schema(
@@ -57,14 +196,15 @@ impl LLMProvider {
         system_prompt: &str,
         user_query: &str,
         schema: Value,
-    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-        match self {
+    ) -> Result<LlmResponse, LlmError> {
+        let started = Instant::now();
+        let response = match self {
             LLMProvider::Gemini(client) => {
                 use rig::providers::gemini::completion::gemini_api_types::{
                     AdditionalParameters, GenerationConfig, Schema,
                 };
 
-                let schema: Schema = serde_json::from_value(schema)?;
+                let schema = Schema::try_from(schema)?;
 
                 let generation_config = GenerationConfig {
                     response_mime_type: Some("application/json".to_string()),
@@ -82,25 +222,97 @@ impl LLMProvider {
                     .additional_params(serde_json::to_value(additional_params)?)
                     .build();
 
-                let response = agent.prompt(user_query).await?;
-                Ok(response)
+                agent.prompt(user_query).extended_details().await?
             }
             LLMProvider::OpenAI(client) => {
+                use rig::providers::openai::responses_api::{
+                    AdditionalParameters, StructuredOutputsInput, TextConfig, TextFormat,
+                };
+
+                // Responses API structured output. Not strict, since strict mode requires every
+                // property to be required and the query schema has optional fields.
+                let additional_params = AdditionalParameters {
+                    text: Some(TextConfig {
+                        format: TextFormat::JsonSchema(StructuredOutputsInput {
+                            name: STRUCTURED_OUTPUT_TOOL.to_string(),
+                            schema,
+                            strict: false,
+                        }),
+                    }),
+                    ..Default::default()
+                };
+
                 let agent = client
                     .agent(OPENAI_MODEL)
                     .preamble(system_prompt)
+                    .additional_params(serde_json::to_value(additional_params)?)
                     .build();
 
-                // OpenAI: use JSON mode or function calling for structured output
-                let prompt = format!(
-                    "{}\n\nRespond with valid JSON matching this schema:\n{}",
-                    user_query,
-                    serde_json::to_string_pretty(&schema)?
-                );
+                agent.prompt(user_query).extended_details().await?
+            }
+            LLMProvider::Anthropic { client, model } => {
+                // Anthropic has no JSON mode, so the model is forced to call a tool whose input
+                // schema is the response schema and the tool input is taken as the answer
+                let response = client
+                    .completion_model(model)
+                    .completion_request(user_query)
+                    .preamble(system_prompt.to_string())
+                    .tool(ToolDefinition {
+                        name: STRUCTURED_OUTPUT_TOOL.to_string(),
+                        description: "Return the response as structured data".to_string(),
+                        parameters: schema,
+                    })
+                    .tool_choice(ToolChoice::Specific {
+                        function_names: vec![STRUCTURED_OUTPUT_TOOL.to_string()],
+                    })
+                    .max_tokens(ANTHROPIC_MAX_TOKENS)
+                    .temperature(0.0)
+                    .send()
+                    .await?;
+
+                let text = response
+                    .choice
+                    .iter()
+                    .find_map(|content| match content {
+                        AssistantContent::ToolCall(call) => Some(call.function.arguments.to_string()),
+                        _ => None,
+                    })
+                    .ok_or("Anthropic response did not call the respond tool")?;
 
-                let response = agent.prompt(&prompt).await?;
-                Ok(response)
+                return Ok(LlmResponse {
+                    text,
+                    usage: self.usage(response.usage, started),
+                    hedge_usage: None,
+                });
             }
-        }
+            #[cfg(test)]
+            LLMProvider::Stub(stub) => return stub.respond(started).await,
+            LLMProvider::Ollama { client, model } | LLMProvider::OpenAICompatible { client, model } => {
+                // Chat Completions JSON schema mode, also understood by Ollama, vLLM and LM Studio
+                let agent = AgentBuilder::new(client.completion_model(model).completions_api())
+                    .preamble(system_prompt)
+                    .temperature(0.0)
+                    .additional_params(json!({
+                        "response_format": {
+                            "type": "json_schema",
+                            "json_schema": {
+                                "name": STRUCTURED_OUTPUT_TOOL,
+                                "schema": schema,
+                            },
+                        },
+                    }))
+                    .build();
+
+                agent.prompt(user_query).extended_details().await?
+            }
+        };
+        Ok(self.response(response, started))
     }
 }
+
+fn env_or(var: &str, default: &str) -> String {
+    std::env::var(var)
+        .ok()
+        .filter(|v| !v.trim().is_empty())
+        .unwrap_or_else(|| default.to_string())
+}
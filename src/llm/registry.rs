@@ -0,0 +1,235 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use utoipa::ToSchema;
+
+use super::prompts::{QUERY_PROMPT, QUERY_PROMPT_VERSION, SQL_PROMPT, SQL_PROMPT_VERSION};
+
+pub const QUERY_PROMPT_ID: &str = "query";
+pub const SQL_PROMPT_ID: &str = "sql";
+
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct PromptRef {
+    pub id: String,
+    pub version: String,
+}
+
+pub struct PromptTemplate {
+    pub id: String,
+    pub version: String,
+    pub text: String,
+}
+
+impl PromptTemplate {
+    pub fn reference(&self) -> PromptRef {
+        PromptRef {
+            id: self.id.clone(),
+            version: self.version.clone(),
+        }
+    }
+
+    // Fills {{name}} placeholders; templates without them are returned unchanged
+    pub fn render(&self, vars: &[(&str, &str)]) -> String {
+        vars.iter().fold(self.text.clone(), |text, (name, value)| {
+            text.replace(&format!("{{{{{}}}}}", name), value)
+        })
+    }
+}
+
+// Prompt templates loaded from <dir>/<id>/<version>.txt, e.g. prompts/query/v2.txt.
+// Which version serves a request is, in order: the version the request asks for, a
+// weighted pick from PROMPT_ROLLOUT_<ID> (e.g. PROMPT_ROLLOUT_QUERY="v1:90,v2:10"),
+// or the highest version on disk.
+pub struct PromptRegistry {
+    prompts: HashMap<String, Vec<PromptTemplate>>,
+    rollouts: HashMap<String, Vec<(String, u64)>>,
+}
+
+impl PromptRegistry {
+    pub fn load(dir: &str) -> Self {
+        let mut prompts: HashMap<String, Vec<PromptTemplate>> = HashMap::new();
+
+        for id in [QUERY_PROMPT_ID, SQL_PROMPT_ID] {
+            let path = Path::new(dir).join(id);
+            let entries = match std::fs::read_dir(&path) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    eprintln!("Failed to read prompt directory {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+
+            for entry in entries.flatten() {
+                let file = entry.path();
+                if file.extension().and_then(|e| e.to_str()) != Some("txt") {
+                    continue;
+                }
+                let Some(version) = file.file_stem().and_then(|s| s.to_str()) else {
+                    continue;
+                };
+                match std::fs::read_to_string(&file) {
+                    Ok(text) => prompts.entry(id.to_string()).or_default().push(PromptTemplate {
+                        id: id.to_string(),
+                        version: version.to_string(),
+                        text,
+                    }),
+                    Err(e) => eprintln!("Failed to read prompt {}: {}", file.display(), e),
+                }
+            }
+        }
+
+        for (id, version, text) in [
+            (QUERY_PROMPT_ID, QUERY_PROMPT_VERSION, QUERY_PROMPT),
+            (SQL_PROMPT_ID, SQL_PROMPT_VERSION, SQL_PROMPT),
+        ] {
+            let versions = prompts.entry(id.to_string()).or_default();
+            if !versions.iter().any(|p| p.version == version) {
+                versions.push(PromptTemplate {
+                    id: id.to_string(),
+                    version: version.to_string(),
+                    text: text.to_string(),
+                });
+            }
+        }
+
+        for versions in prompts.values_mut() {
+            versions.sort_by_key(|p| version_number(&p.version));
+        }
+
+        let mut rollouts = HashMap::new();
+        for id in prompts.keys() {
+            let var = format!("PROMPT_ROLLOUT_{}", id.to_uppercase());
+            if let Ok(value) = std::env::var(&var) {
+                match parse_rollout(&value) {
+                    Ok(weights) => {
+                        rollouts.insert(id.clone(), weights);
+                    }
+                    Err(e) => eprintln!("Ignoring {}: {}", var, e),
+                }
+            }
+        }
+
+        for (id, versions) in &prompts {
+            let names: Vec<&str> = versions.iter().map(|p| p.version.as_str()).collect();
+            println!("prompt '{}' versions: {:?}", id, names);
+        }
+
+        PromptRegistry { prompts, rollouts }
+    }
+
+    pub fn select(&self, id: &str, requested: Option<&str>, key: &str) -> Result<&PromptTemplate, String> {
+        let versions = self
+            .prompts
+            .get(id)
+            .filter(|v| !v.is_empty())
+            .ok_or_else(|| format!("Unknown prompt '{}'", id))?;
+
+        let find = |version: &str| versions.iter().find(|p| p.version == version);
+
+        if let Some(version) = requested {
+            return find(version).ok_or_else(|| {
+                let available: Vec<&str> = versions.iter().map(|p| p.version.as_str()).collect();
+                format!("Unknown {} prompt version '{}', available: {}", id, version, available.join(", "))
+            });
+        }
+
+        if let Some(weights) = self.rollouts.get(id) {
+            let total: u64 = weights.iter().map(|(_, w)| w).sum();
+            if total > 0 {
+                let mut bucket = rollout_hash(key) % total;
+                for (version, weight) in weights {
+                    if bucket < *weight {
+                        if let Some(prompt) = find(version) {
+                            return Ok(prompt);
+                        }
+                        break;
+                    }
+                    bucket -= weight;
+                }
+            }
+        }
+
+        Ok(versions.last().expect("prompt versions are not empty"))
+    }
+}
+
+fn version_number(version: &str) -> u64 {
+    version.trim_start_matches('v').parse().unwrap_or(0)
+}
+
+fn parse_rollout(value: &str) -> Result<Vec<(String, u64)>, String> {
+    value
+        .split(',')
+        .filter(|part| !part.trim().is_empty())
+        .map(|part| {
+            let (version, weight) = part
+                .split_once(':')
+                .ok_or_else(|| format!("expected 'version:weight', got '{}'", part))?;
+            let weight = weight
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid weight in '{}'", part))?;
+            Ok((version.trim().to_string(), weight))
+        })
+        .collect()
+}
+
+// FNV-1a over the key alone, so the same key always lands on the same version, across
+// requests and restarts
+fn rollout_hash(key: &str) -> u64 {
+    key.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_rollout(rollout: &str) -> PromptRegistry {
+        let versions = ["v1", "v2"]
+            .iter()
+            .map(|version| PromptTemplate {
+                id: QUERY_PROMPT_ID.to_string(),
+                version: version.to_string(),
+                text: String::new(),
+            })
+            .collect();
+        PromptRegistry {
+            prompts: HashMap::from([(QUERY_PROMPT_ID.to_string(), versions)]),
+            rollouts: HashMap::from([(QUERY_PROMPT_ID.to_string(), parse_rollout(rollout).unwrap())]),
+        }
+    }
+
+    fn version(registry: &PromptRegistry, key: &str) -> String {
+        registry.select(QUERY_PROMPT_ID, None, key).unwrap().version.clone()
+    }
+
+    #[test]
+    fn rollout_is_sticky_per_key() {
+        let registry = with_rollout("v1:50,v2:50");
+        for key in ["lebron 30 points", "", "curry threes"] {
+            let first = version(&registry, key);
+            for _ in 0..20 {
+                assert_eq!(version(&registry, key), first, "{}", key);
+            }
+        }
+    }
+
+    #[test]
+    fn rollout_splits_keys_by_weight() {
+        let registry = with_rollout("v1:50,v2:50");
+        let v2 = (0..1000).filter(|i| version(&registry, &format!("query {}", i)) == "v2").count();
+        assert!((400..=600).contains(&v2), "{}", v2);
+
+        let registry = with_rollout("v1:0,v2:1");
+        assert_eq!(version(&registry, "anything"), "v2");
+    }
+
+    #[test]
+    fn requested_version_overrides_rollout() {
+        let registry = with_rollout("v1:1");
+        assert_eq!(registry.select(QUERY_PROMPT_ID, Some("v2"), "key").unwrap().version, "v2");
+        assert!(registry.select(QUERY_PROMPT_ID, Some("v9"), "key").is_err());
+    }
+}
@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use utoipa::ToSchema;
+
+use super::config::{ANTHROPIC_MODEL, GEMINI_MODEL, OPENAI_MODEL};
+
+// USD per million input and output tokens, from the providers' published price lists.
+// Local models (Ollama, self-hosted OpenAI-compatible servers) have no entry and no cost.
+const PRICES: &[(&str, f64, f64)] = &[
+    (GEMINI_MODEL, 1.25, 10.0),
+    (OPENAI_MODEL, 0.25, 2.0),
+    (ANTHROPIC_MODEL, 3.0, 15.0),
+    ("claude-opus-4-1", 15.0, 75.0),
+    ("claude-haiku-4-5", 1.0, 5.0),
+];
+
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct LlmUsage {
+    pub provider: String,
+    pub model: String,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub latency_ms: u64,
+    // None for models missing from the price table
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost_usd: Option<f64>,
+}
+
+impl LlmUsage {
+    pub fn new(provider: &str, model: &str, input_tokens: u64, output_tokens: u64, latency_ms: u64) -> Self {
+        LlmUsage {
+            provider: provider.to_string(),
+            model: model.to_string(),
+            input_tokens,
+            output_tokens,
+            latency_ms,
+            cost_usd: estimate_cost(model, input_tokens, output_tokens),
+        }
+    }
+}
+
+pub struct LlmResponse {
+    pub text: String,
+    pub usage: LlmUsage,
+    // In hedged mode, the usage of the request that lost the race, sent once it finishes
+    pub hedge_usage: Option<oneshot::Receiver<LlmUsage>>,
+}
+
+pub fn estimate_cost(model: &str, input_tokens: u64, output_tokens: u64) -> Option<f64> {
+    PRICES
+        .iter()
+        .find(|(m, _, _)| *m == model)
+        .map(|(_, input, output)| (input_tokens as f64 * input + output_tokens as f64 * output) / 1_000_000.0)
+}
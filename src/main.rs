@@ -1,6 +1,3 @@
-mod api;
-mod llm;
-
 use axum::{routing::{get, post}, Router};
 use std::sync::Arc;
 use tokio_postgres::NoTls;
@@ -8,18 +5,25 @@ use tower_http::cors::{CorsLayer, Any};
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
-use api::boxscores::{BoxScore, CountResponse, get_boxscores, get_count};
+use player_stats_backend::{api, llm};
+
+use api::boxscores::{BoxScore, CountResponse, GroupCount, get_boxscores, get_count, search_boxscores};
+use api::catalog::{get_schema, post_schema_refresh, SchemaCatalog};
+use api::examples::{get_examples, post_example, ExampleStore};
+use api::players::PlayerResolver;
 use api::query::{post_query, AppState};
 use api::sql::post_sql;
-use llm::get_provider;
+use api::usage::{create_usage_table, get_llm_usage};
+use llm::{get_provider, PromptRegistry};
 
 #[derive(OpenApi)]
 #[openapi(
     paths(
         api::boxscores::routes::get_count,
-        api::boxscores::routes::get_boxscores
+        api::boxscores::routes::get_boxscores,
+        api::boxscores::routes::search_boxscores
     ),
-    components(schemas(CountResponse, BoxScore))
+    components(schemas(CountResponse, GroupCount, BoxScore))
 )]
 struct ApiDoc;
 
@@ -29,6 +33,9 @@ async fn main() {
         .expect("DATABASE_URL must be set");
 
     let llm_provider = get_provider();
+    if llm_provider.is_none() {
+        println!("no LLM provider configured, /api/query will use the rule-based parser and /api/sql is disabled");
+    }
 
     let (db_client, connection) = tokio_postgres::connect(&database_url, NoTls)
         .await
@@ -53,10 +60,30 @@ async fn main() {
         }
     });
 
+    let schema_catalog = SchemaCatalog::default();
+    if let Err(e) = schema_catalog.refresh(&readonly_db_client).await {
+        eprintln!("{}, the SQL prompt will use the built-in column list", e);
+    }
+
+    let examples = ExampleStore::default();
+    if let Err(e) = examples.load(&db_client).await {
+        eprintln!("{}, using the built-in examples without persistence", e);
+    }
+
+    if let Err(e) = create_usage_table(&db_client).await {
+        eprintln!("{}, LLM usage will not be recorded", e);
+    }
+
+    let prompts_dir = std::env::var("PROMPTS_DIR").unwrap_or_else(|_| "prompts".to_string());
+
     let state = Arc::new(AppState {
         llm_provider,
         db_client: Arc::new(db_client),
         readonly_db_client: Arc::new(readonly_db_client),
+        player_resolver: Arc::new(PlayerResolver::default()),
+        schema_catalog: Arc::new(schema_catalog),
+        examples: Arc::new(examples),
+        prompts: PromptRegistry::load(&prompts_dir),
     });
 
     let cors = CorsLayer::new()
@@ -67,8 +94,13 @@ async fn main() {
     let app: Router = Router::new()
         .route("/api/boxscores/count", get(get_count))
         .route("/api/boxscores", get(get_boxscores))
+        .route("/api/boxscores/search", post(search_boxscores))
         .route("/api/query", post(post_query))
         .route("/api/sql", post(post_sql))
+        .route("/api/examples", get(get_examples).post(post_example))
+        .route("/api/admin/llm-usage", get(get_llm_usage))
+        .route("/api/schema", get(get_schema))
+        .route("/api/schema/refresh", post(post_schema_refresh))
         .with_state(state)
         .layer(cors)
         .merge(SwaggerUi::new("/docs").url("/api-docs/openapi.json", ApiDoc::openapi()));